@@ -0,0 +1,18 @@
+use crate::ast::indices::NameIdx;
+
+/// Attributes the language gives a meaning to, others are only kept
+pub const BUILTIN_ATTRIBUTES: &[&str] = &["test", "inline", "memo", "deprecated"];
+
+/// An attribute written before a function, e.g. `#[deprecated("use sum")]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: NameIdx,
+    pub args: Vec<AttributeArg>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeArg {
+    Int(i64),
+    Str(String),
+    Name(NameIdx),
+}
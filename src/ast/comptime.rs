@@ -0,0 +1,6 @@
+/// Where a `comptime` block was written, for the errors of its evaluation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComptimeBlock {
+    pub line: usize,
+    pub column: usize,
+}
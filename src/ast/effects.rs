@@ -0,0 +1,10 @@
+use crate::ast::indices::NameIdx;
+
+/// An effect declared with `effect Ask : (Int) -> Int`. The types are names
+/// kept for printing the declaration, only the number of parameters is
+/// checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectDecl {
+    pub params: Vec<NameIdx>,
+    pub result: NameIdx,
+}
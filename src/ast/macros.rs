@@ -0,0 +1,11 @@
+use crate::ast::indices::{AstIdx, NameIdx};
+
+/// A macro defined with `macro name(a, b) { template }`. The template is
+/// parsed like the body of a function taking the arguments, and calls get a
+/// copy of it with the references to the arguments replaced by the
+/// expressions they were given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroDef {
+    pub params: Vec<NameIdx>,
+    pub body_idx: AstIdx,
+}
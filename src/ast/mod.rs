@@ -1,11 +1,23 @@
+pub mod attributes;
+pub mod comptime;
+pub mod effects;
 pub mod indices;
+pub mod macros;
+pub mod operators;
+pub mod params;
 pub mod pool;
 pub mod primitives;
 
 pub mod pretty_printer;
 
 // Re-export main types for convenient usage
+pub use self::attributes::{Attribute, AttributeArg};
+pub use self::comptime::ComptimeBlock;
+pub use self::effects::EffectDecl;
 pub use self::indices::{AstIdx, FunIdx, NameIdx, ParamIdx};
+pub use self::macros::MacroDef;
+pub use self::operators::{Fixity, OperatorDef};
+pub use self::params::{NamedArgs, Param};
 pub use self::pool::AstPool;
 pub use self::primitives::PrimitiveFunc;
 
@@ -32,8 +44,46 @@ pub enum Ast {
     FunctionDef {
         name_idx: NameIdx,
         param_count: usize,
+        /// The last parameter collects the remaining arguments into a list
+        variadic: bool,
         body_idx: AstIdx,
     },
+    ConstDef {
+        name_idx: NameIdx,
+        body_idx: AstIdx,
+    },
+    /// Postfix `?`: the value inside an Option or Result, returning early
+    /// from the enclosing function on `none` and `err(e)`
+    Try {
+        body_idx: AstIdx,
+    },
+    /// `try { body } catch err { handler }`, the handler is a lambda called
+    /// with the error when evaluating the body fails
+    TryCatch {
+        body_idx: AstIdx,
+        handler_idx: AstIdx,
+    },
+    /// Guarded clauses of the function `name_idx`, tried in order. The
+    /// children are pairs of a condition and its value, followed by the
+    /// value of the catch-all clause when their count is odd.
+    Guards {
+        name_idx: NameIdx,
+        child_start: AstIdx,
+        child_count: usize,
+        len: usize,
+    },
+    /// A call of a macro as it was written, followed by its expansion,
+    /// which is what runs. The call is only kept for printing.
+    MacroCall {
+        call_idx: AstIdx,
+        expansion_idx: AstIdx,
+    },
+    /// A `comptime` block as it was written, followed by the literal of the
+    /// value it had when the program was loaded, which is what runs
+    Comptime {
+        block_idx: AstIdx,
+        value_idx: AstIdx,
+    },
 }
 
 #[cfg(test)]
@@ -88,6 +138,20 @@ mod tests {
                 pool.get_string(name_idx),
                 param_count
             ),
+            Ast::ConstDef { name_idx, .. } => format!("ConstDef({})", pool.get_string(name_idx)),
+            Ast::Try { .. } => "Try".to_string(),
+            Ast::TryCatch { .. } => "TryCatch".to_string(),
+            Ast::MacroCall { call_idx, .. } => format!("MacroCall({:?})", call_idx),
+            Ast::Comptime { block_idx, .. } => format!("Comptime({:?})", block_idx),
+            Ast::Guards {
+                name_idx,
+                child_count,
+                ..
+            } => format!(
+                "Guards({}), with {} children",
+                pool.get_string(name_idx),
+                child_count
+            ),
         };
 
         result.push(format!("{}{:?}: {}", indent, node_idx, node_desc));
@@ -0,0 +1,43 @@
+use crate::ast::indices::NameIdx;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixity {
+    Left,
+    Right,
+    None,
+}
+
+impl Fixity {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Fixity::Left => "infixl",
+            Fixity::Right => "infixr",
+            Fixity::None => "infix",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperatorDef {
+    pub fixity: Fixity,
+    pub precedence: u8,
+    /// Function applied to the operands, either a primitive or a user function
+    pub func_name: NameIdx,
+}
+
+/// Operators every pool starts with: symbol, fixity, precedence and function
+pub const BUILTIN_OPERATORS: &[(&str, Fixity, u8, &str)] = &[
+    ("|>", Fixity::Left, 1, "pipe"),
+    ("==", Fixity::None, 4, "equal"),
+    ("<", Fixity::None, 4, "less"),
+    (">", Fixity::None, 4, "greater"),
+    ("..", Fixity::None, 5, "range"),
+    ("..=", Fixity::None, 5, "range_inclusive"),
+    ("+", Fixity::Left, 6, "add"),
+    ("*", Fixity::Left, 7, "multiply"),
+    (">>", Fixity::Left, 9, "compose"),
+];
+
+pub fn is_builtin_operator(symbol: &str) -> bool {
+    BUILTIN_OPERATORS.iter().any(|&(s, ..)| s == symbol)
+}
@@ -0,0 +1,21 @@
+use crate::ast::indices::{AstIdx, NameIdx};
+
+/// A parameter of a user function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Param {
+    pub name: NameIdx,
+    /// Expression evaluated for calls that leave the parameter out, which
+    /// cannot use the parameters of the function
+    pub default: Option<AstIdx>,
+}
+
+/// The named arguments of a call, which follow its positional arguments in
+/// the order they were written. They are placed at their parameters when
+/// the call is compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedArgs {
+    pub callee: NameIdx,
+    /// Number of positional arguments before the named ones
+    pub positional: usize,
+    pub names: Vec<NameIdx>,
+}
@@ -1,4 +1,10 @@
+use crate::ast::attributes::Attribute;
+use crate::ast::comptime::ComptimeBlock;
+use crate::ast::effects::EffectDecl;
 use crate::ast::indices::{AstIdx, NameIdx, ParamIdx};
+use crate::ast::macros::MacroDef;
+use crate::ast::operators::{BUILTIN_OPERATORS, Fixity, OperatorDef};
+use crate::ast::params::{NamedArgs, Param};
 use crate::ast::primitives::PrimitiveFunc;
 use std::collections::{HashMap, HashSet};
 use std::ops::{Index, IndexMut};
@@ -6,13 +12,36 @@ use std::path::Path;
 
 use super::Ast;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AstPool {
     pub nodes: Vec<Ast>,
 
     string_pool: Vec<String>,
     string_map: HashMap<String, NameIdx>,
     pub function_defs: HashMap<NameIdx, AstIdx>,
+    pub const_defs: HashMap<NameIdx, AstIdx>,
+    /// Infix operators by symbol, including the built-in ones
+    pub operators: HashMap<NameIdx, OperatorDef>,
+    /// Parameters of the user functions, in order
+    pub function_params: HashMap<NameIdx, Vec<Param>>,
+    /// Names of the rest parameters of the variadic user functions
+    pub rest_params: HashMap<NameIdx, NameIdx>,
+    /// Calls written with named arguments, by call node
+    pub named_args: HashMap<AstIdx, NamedArgs>,
+    /// Names of the `where` bindings of the functions that have them. The
+    /// body of such a function calls a lambda taking the bindings in order.
+    pub where_bindings: HashMap<NameIdx, Vec<NameIdx>>,
+    /// Effects declared with `effect`, by name
+    pub effects: HashMap<NameIdx, EffectDecl>,
+    /// Macros defined with `macro`, by name
+    pub macros: HashMap<NameIdx, MacroDef>,
+    /// Where the `comptime` blocks were written, by call node
+    pub comptime_blocks: HashMap<AstIdx, ComptimeBlock>,
+    /// Attributes of the functions that have them, in written order
+    pub attributes: HashMap<NameIdx, Vec<Attribute>>,
+    /// Text of the `///` comments before the functions that have them,
+    /// without the slashes
+    pub docs: HashMap<NameIdx, String>,
 }
 
 impl AstPool {
@@ -21,7 +50,7 @@ impl AstPool {
         let mut visited = HashSet::new();
 
         if let Some(name_idx) = self.get_name_idx_from_func(function_name) {
-            if let Some(&ast_idx) = self.function_defs.get(&name_idx) {
+            if let Some(ast_idx) = self.get_global_def(name_idx) {
                 self.find_dependencies_recursive(ast_idx, &mut dependencies, &mut visited);
             }
         }
@@ -29,6 +58,47 @@ impl AstPool {
         dependencies
     }
 
+    /// The attribute `name` of a function, if it has it
+    pub fn attribute(&self, func_name: NameIdx, name: &str) -> Option<&Attribute> {
+        self.attributes
+            .get(&func_name)?
+            .iter()
+            .find(|attribute| self.get_string(attribute.name) == name)
+    }
+
+    /// Definition of a top-level function or constant
+    pub fn get_global_def(&self, name_idx: NameIdx) -> Option<AstIdx> {
+        self.function_defs
+            .get(&name_idx)
+            .or_else(|| self.const_defs.get(&name_idx))
+            .copied()
+    }
+
+    /// Constants ordered so that every constant comes after the constants it
+    /// depends on, directly or through functions. A constant depending on
+    /// itself is returned as the error.
+    pub fn const_eval_order(&self) -> Result<Vec<NameIdx>, NameIdx> {
+        let mut order = Vec::with_capacity(self.const_defs.len());
+
+        for &name_idx in self.const_defs.keys() {
+            let dependencies = self.find_dependencies(self.get_string(name_idx));
+            if dependencies.contains(&name_idx) {
+                return Err(name_idx);
+            }
+
+            let const_deps = dependencies
+                .iter()
+                .filter(|dep| self.const_defs.contains_key(dep))
+                .count();
+            order.push((const_deps, name_idx));
+        }
+
+        // Dependencies are transitive, so without cycles a constant has
+        // strictly more constant dependencies than any of them
+        order.sort_by_key(|&(const_deps, name_idx)| (const_deps, name_idx.0));
+        Ok(order.into_iter().map(|(_, name_idx)| name_idx).collect())
+    }
+
     fn find_dependencies_recursive(
         &self,
         node_idx: AstIdx,
@@ -42,15 +112,21 @@ impl AstPool {
 
         match self[node_idx] {
             Ast::UserFunc(name_idx) => {
-                if let Some(&func_ast_idx) = self.function_defs.get(&name_idx) {
-                    if dependencies.insert(name_idx) {
-                        self.find_dependencies_recursive(func_ast_idx, dependencies, visited);
-                    }
+                if let Some(func_ast_idx) = self.get_global_def(name_idx)
+                    && dependencies.insert(name_idx)
+                {
+                    self.find_dependencies_recursive(func_ast_idx, dependencies, visited);
                 }
             }
             Ast::Call { func_idx, .. } => {
                 self.find_dependencies_recursive(func_idx, dependencies, visited);
             }
+            Ast::FunctionDef { name_idx, .. } => {
+                let defaults = self.function_params.get(&name_idx).into_iter().flatten();
+                for default in defaults.filter_map(|param| param.default) {
+                    self.find_dependencies_recursive(default, dependencies, visited);
+                }
+            }
             _ => {}
         }
 
@@ -62,12 +138,29 @@ impl AstPool {
     }
 
     pub fn new() -> Self {
-        Self {
+        let mut pool = Self {
             nodes: Vec::new(),
             string_pool: Vec::new(),
             string_map: HashMap::new(),
             function_defs: HashMap::new(),
+            const_defs: HashMap::new(),
+            operators: HashMap::new(),
+            function_params: HashMap::new(),
+            rest_params: HashMap::new(),
+            named_args: HashMap::new(),
+            where_bindings: HashMap::new(),
+            effects: HashMap::new(),
+            macros: HashMap::new(),
+            comptime_blocks: HashMap::new(),
+            attributes: HashMap::new(),
+            docs: HashMap::new(),
+        };
+
+        for &(symbol, fixity, precedence, func_name) in BUILTIN_OPERATORS {
+            pool.add_operator_def(symbol, fixity, precedence, func_name);
         }
+
+        pool
     }
 
     pub fn intern_string(&mut self, s: &str) -> NameIdx {
@@ -148,12 +241,19 @@ impl AstPool {
         self.add_call(id, child_start, 2, len)
     }
 
-    pub fn add_function_def(&mut self, name: &str, param_count: usize, body_idx: AstIdx) -> AstIdx {
+    pub fn add_function_def(
+        &mut self,
+        name: &str,
+        param_count: usize,
+        variadic: bool,
+        body_idx: AstIdx,
+    ) -> AstIdx {
         let name_idx = self.intern_string(name);
         let node_idx = AstIdx(self.nodes.len());
         self.nodes.push(Ast::FunctionDef {
             name_idx,
             param_count,
+            variadic,
             body_idx,
         });
 
@@ -162,10 +262,717 @@ impl AstPool {
         node_idx
     }
 
+    pub fn add_function_params(&mut self, name: &str, params: Vec<Param>, rest: Option<NameIdx>) {
+        let name_idx = self.intern_string(name);
+        self.function_params.insert(name_idx, params);
+        match rest {
+            Some(rest) => self.rest_params.insert(name_idx, rest),
+            None => self.rest_params.remove(&name_idx),
+        };
+    }
+
+    /// Append a copy of the subtree rooted at `root` and return the root of
+    /// the copy. Parameter levels are raised by `level_shift` so the copy can
+    /// be placed in a more deeply nested scope.
+    pub fn copy_subtree(&mut self, root: AstIdx, level_shift: usize) -> AstIdx {
+        self.copy_shifted(root, 0, level_shift)
+    }
+
+    /// Like [`Self::copy_subtree`], leaving the levels of the parameters
+    /// from `min_level` down as they are
+    fn copy_shifted(&mut self, root: AstIdx, min_level: usize, level_shift: usize) -> AstIdx {
+        let start = root.0 + 1 - self.len(root);
+        let delta = self.nodes.len() - start;
+        let shift = |idx: AstIdx| AstIdx(idx.0 + delta);
+
+        for i in start..=root.0 {
+            let node = match self.nodes[i] {
+                Ast::ParamRef {
+                    name,
+                    level,
+                    offset,
+                } => Ast::ParamRef {
+                    name,
+                    level: if level > min_level {
+                        level + level_shift
+                    } else {
+                        level
+                    },
+                    offset,
+                },
+                Ast::Lambda {
+                    param_count,
+                    body_idx,
+                } => Ast::Lambda {
+                    param_count,
+                    body_idx: shift(body_idx),
+                },
+                Ast::Call {
+                    func_idx,
+                    child_start,
+                    child_count,
+                    len,
+                } => Ast::Call {
+                    func_idx: shift(func_idx),
+                    child_start: shift(child_start),
+                    child_count,
+                    len,
+                },
+                Ast::FunctionDef {
+                    name_idx,
+                    param_count,
+                    variadic,
+                    body_idx,
+                } => Ast::FunctionDef {
+                    name_idx,
+                    param_count,
+                    variadic,
+                    body_idx: shift(body_idx),
+                },
+                Ast::ConstDef { name_idx, body_idx } => Ast::ConstDef {
+                    name_idx,
+                    body_idx: shift(body_idx),
+                },
+                Ast::Try { body_idx } => Ast::Try {
+                    body_idx: shift(body_idx),
+                },
+                Ast::TryCatch {
+                    body_idx,
+                    handler_idx,
+                } => Ast::TryCatch {
+                    body_idx: shift(body_idx),
+                    handler_idx: shift(handler_idx),
+                },
+                Ast::Guards {
+                    name_idx,
+                    child_start,
+                    child_count,
+                    len,
+                } => Ast::Guards {
+                    name_idx,
+                    child_start: shift(child_start),
+                    child_count,
+                    len,
+                },
+                Ast::MacroCall {
+                    call_idx,
+                    expansion_idx,
+                } => Ast::MacroCall {
+                    call_idx: shift(call_idx),
+                    expansion_idx: shift(expansion_idx),
+                },
+                Ast::Comptime {
+                    block_idx,
+                    value_idx,
+                } => Ast::Comptime {
+                    block_idx: shift(block_idx),
+                    value_idx: shift(value_idx),
+                },
+                node @ (Ast::Integer(_) | Ast::PrimitiveFunc(_) | Ast::UserFunc(_)) => node,
+            };
+            self.copy_annotations(AstIdx(i), AstIdx(self.nodes.len()));
+            self.nodes.push(node);
+        }
+
+        shift(root)
+    }
+
+    /// Append a copy of the subtree rooted at `root` to another pool and
+    /// return the root of the copy. Names are interned in `target` and
+    /// `param` gives the level and offset of every parameter reference.
+    pub fn copy_into(
+        &self,
+        root: AstIdx,
+        target: &mut AstPool,
+        param: impl Fn(usize, ParamIdx) -> (usize, ParamIdx),
+    ) -> AstIdx {
+        let start = root.0 + 1 - self.len(root);
+        let base = target.nodes.len();
+        let shift = |idx: AstIdx| AstIdx(idx.0 - start + base);
+
+        for i in start..=root.0 {
+            let node = match self.nodes[i] {
+                Ast::ParamRef {
+                    name,
+                    level,
+                    offset,
+                } => {
+                    let (level, offset) = param(level, offset);
+                    Ast::ParamRef {
+                        name: target.intern_string(self.get_string(name)),
+                        level,
+                        offset,
+                    }
+                }
+                Ast::UserFunc(name_idx) => {
+                    let target_idx = target.intern_string(self.get_string(name_idx));
+                    if let Some(decl) = self.effects.get(&name_idx) {
+                        target.effects.insert(target_idx, decl.clone());
+                    }
+                    Ast::UserFunc(target_idx)
+                }
+                Ast::Lambda {
+                    param_count,
+                    body_idx,
+                } => Ast::Lambda {
+                    param_count,
+                    body_idx: shift(body_idx),
+                },
+                Ast::Call {
+                    func_idx,
+                    child_start,
+                    child_count,
+                    len,
+                } => Ast::Call {
+                    func_idx: shift(func_idx),
+                    child_start: shift(child_start),
+                    child_count,
+                    len,
+                },
+                Ast::FunctionDef {
+                    name_idx,
+                    param_count,
+                    variadic,
+                    body_idx,
+                } => Ast::FunctionDef {
+                    name_idx: target.intern_string(self.get_string(name_idx)),
+                    param_count,
+                    variadic,
+                    body_idx: shift(body_idx),
+                },
+                Ast::ConstDef { name_idx, body_idx } => Ast::ConstDef {
+                    name_idx: target.intern_string(self.get_string(name_idx)),
+                    body_idx: shift(body_idx),
+                },
+                Ast::Try { body_idx } => Ast::Try {
+                    body_idx: shift(body_idx),
+                },
+                Ast::TryCatch {
+                    body_idx,
+                    handler_idx,
+                } => Ast::TryCatch {
+                    body_idx: shift(body_idx),
+                    handler_idx: shift(handler_idx),
+                },
+                Ast::Guards {
+                    name_idx,
+                    child_start,
+                    child_count,
+                    len,
+                } => Ast::Guards {
+                    name_idx: target.intern_string(self.get_string(name_idx)),
+                    child_start: shift(child_start),
+                    child_count,
+                    len,
+                },
+                Ast::MacroCall {
+                    call_idx,
+                    expansion_idx,
+                } => Ast::MacroCall {
+                    call_idx: shift(call_idx),
+                    expansion_idx: shift(expansion_idx),
+                },
+                Ast::Comptime {
+                    block_idx,
+                    value_idx,
+                } => Ast::Comptime {
+                    block_idx: shift(block_idx),
+                    value_idx: shift(value_idx),
+                },
+                node @ (Ast::Integer(_) | Ast::PrimitiveFunc(_)) => node,
+            };
+            target.nodes.push(node);
+        }
+
+        shift(root)
+    }
+
+    /// Append the expansion of the macro call `call_idx`, made in a scope of
+    /// `level`, followed by a node keeping both
+    pub fn add_macro_call(
+        &mut self,
+        macro_def: &MacroDef,
+        call_idx: AstIdx,
+        level: usize,
+    ) -> AstIdx {
+        let args = self.children(call_idx).unwrap_or_default();
+        let expansion_idx = self.expand(macro_def.body_idx, &args, level, 0);
+
+        let node_idx = AstIdx(self.nodes.len());
+        self.nodes.push(Ast::MacroCall {
+            call_idx,
+            expansion_idx,
+        });
+        node_idx
+    }
+
+    /// Append a copy of the part of a macro template at `node`, nested in
+    /// `depth` lambdas of the template. The macro parameters are at level 1
+    /// and are replaced by copies of the arguments. Parameters of the
+    /// template and of the arguments are resolved by level and offset, so
+    /// neither can capture the names of the other.
+    fn expand(&mut self, node: AstIdx, args: &[AstIdx], level: usize, depth: usize) -> AstIdx {
+        let expanded = match self.nodes[node.0] {
+            Ast::ParamRef {
+                level: 1, offset, ..
+            } => {
+                // Lambdas of the argument are now nested in the lambdas of
+                // the template around it
+                return self.copy_shifted(args[offset.0], level, depth);
+            }
+            Ast::ParamRef {
+                name,
+                level: param_level,
+                offset,
+            } => Ast::ParamRef {
+                name,
+                level: param_level - 1 + level,
+                offset,
+            },
+            Ast::Lambda {
+                param_count,
+                body_idx,
+            } => Ast::Lambda {
+                param_count,
+                body_idx: self.expand(body_idx, args, level, depth + 1),
+            },
+            Ast::Try { body_idx } => Ast::Try {
+                body_idx: self.expand(body_idx, args, level, depth),
+            },
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => Ast::TryCatch {
+                body_idx: self.expand(body_idx, args, level, depth),
+                handler_idx: self.expand(handler_idx, args, level, depth),
+            },
+            Ast::MacroCall {
+                call_idx,
+                expansion_idx,
+            } => Ast::MacroCall {
+                call_idx: self.expand(call_idx, args, level, depth),
+                expansion_idx: self.expand(expansion_idx, args, level, depth),
+            },
+            Ast::Comptime {
+                block_idx,
+                value_idx,
+            } => Ast::Comptime {
+                block_idx: self.expand(block_idx, args, level, depth),
+                value_idx: self.expand(value_idx, args, level, depth),
+            },
+            Ast::Call {
+                func_idx,
+                child_count,
+                ..
+            } => {
+                // The callee and the arguments keep their order in the pool
+                let start = self.nodes.len();
+                let mut parts = self.children(node).unwrap_or_default();
+                parts.push(func_idx);
+                parts.sort_by_key(|part| part.0);
+
+                let mut new_func_idx = func_idx;
+                let mut child_start = None;
+                for part in parts {
+                    let copy = self.expand(part, args, level, depth);
+                    if part == func_idx {
+                        new_func_idx = copy;
+                    } else {
+                        child_start = Some(copy);
+                    }
+                }
+
+                Ast::Call {
+                    func_idx: new_func_idx,
+                    child_start: child_start.unwrap_or(new_func_idx),
+                    child_count,
+                    len: self.nodes.len() - start,
+                }
+            }
+            // Definitions are not expressions
+            Ast::FunctionDef { .. } | Ast::ConstDef { .. } | Ast::Guards { .. } => {
+                return self.copy_subtree(node, 0);
+            }
+            node @ (Ast::Integer(_) | Ast::PrimitiveFunc(_) | Ast::UserFunc(_)) => node,
+        };
+
+        let node_idx = AstIdx(self.nodes.len());
+        self.copy_annotations(node, node_idx);
+        self.nodes.push(expanded);
+        node_idx
+    }
+
+    /// The `comptime` blocks in the tree at `root` that are not evaluated
+    /// yet, with the level of the scope they are written in. Blocks nested
+    /// in them are evaluated with them.
+    pub fn comptime_calls(&self, root: AstIdx, level: usize) -> Vec<(AstIdx, usize)> {
+        let mut calls = Vec::new();
+        self.collect_comptime_calls(root, level, &mut calls);
+        calls
+    }
+
+    fn collect_comptime_calls(&self, node: AstIdx, level: usize, calls: &mut Vec<(AstIdx, usize)>) {
+        let mut parts = self.children(node).unwrap_or_default();
+        match self[node] {
+            Ast::Call { func_idx, .. } if self.is_comptime(func_idx) => {
+                calls.push((node, level));
+                return;
+            }
+            // Where bindings are given to a lambda in the callee
+            Ast::Call { func_idx, .. } => parts.push(func_idx),
+            Ast::Lambda { body_idx, .. } => {
+                return self.collect_comptime_calls(body_idx, level + 1, calls);
+            }
+            _ => {}
+        }
+        for part in parts {
+            self.collect_comptime_calls(part, level, calls);
+        }
+    }
+
+    fn is_comptime(&self, func_idx: AstIdx) -> bool {
+        matches!(self[func_idx], Ast::PrimitiveFunc(PrimitiveFunc::Comptime))
+    }
+
+    /// Append a copy of the tree at `node` where every `comptime` block
+    /// `literal` appends the literal of a value for is kept by an
+    /// `Ast::Comptime` node, and return the root of the copy
+    pub fn inline_literals(
+        &mut self,
+        node: AstIdx,
+        literal: &mut impl FnMut(&mut AstPool, AstIdx) -> Option<AstIdx>,
+    ) -> AstIdx {
+        let copied = match self.nodes[node.0] {
+            Ast::Call { func_idx, .. } if self.is_comptime(func_idx) => {
+                let block_idx = self.copy_subtree(node, 0);
+                let Some(value_idx) = literal(self, node) else {
+                    return block_idx;
+                };
+                Ast::Comptime {
+                    block_idx,
+                    value_idx,
+                }
+            }
+            Ast::Call {
+                func_idx,
+                child_count,
+                ..
+            } => {
+                // The callee and the arguments keep their order in the pool
+                let start = self.nodes.len();
+                let mut parts = self.children(node).unwrap_or_default();
+                parts.push(func_idx);
+                parts.sort_by_key(|part| part.0);
+
+                let mut new_func_idx = func_idx;
+                let mut child_start = None;
+                for part in parts {
+                    let copy = self.inline_literals(part, literal);
+                    if part == func_idx {
+                        new_func_idx = copy;
+                    } else {
+                        child_start = Some(copy);
+                    }
+                }
+
+                Ast::Call {
+                    func_idx: new_func_idx,
+                    child_start: child_start.unwrap_or(new_func_idx),
+                    child_count,
+                    len: self.nodes.len() - start,
+                }
+            }
+            Ast::Guards {
+                name_idx,
+                child_count,
+                ..
+            } => {
+                let start = self.nodes.len();
+                let mut child_start = AstIdx(start);
+                for child in self.children(node).unwrap_or_default() {
+                    child_start = self.inline_literals(child, literal);
+                }
+
+                Ast::Guards {
+                    name_idx,
+                    child_start,
+                    child_count,
+                    len: self.nodes.len() - start,
+                }
+            }
+            Ast::Lambda {
+                param_count,
+                body_idx,
+            } => Ast::Lambda {
+                param_count,
+                body_idx: self.inline_literals(body_idx, literal),
+            },
+            Ast::FunctionDef {
+                name_idx,
+                param_count,
+                variadic,
+                body_idx,
+            } => Ast::FunctionDef {
+                name_idx,
+                param_count,
+                variadic,
+                body_idx: self.inline_literals(body_idx, literal),
+            },
+            Ast::ConstDef { name_idx, body_idx } => Ast::ConstDef {
+                name_idx,
+                body_idx: self.inline_literals(body_idx, literal),
+            },
+            Ast::Try { body_idx } => Ast::Try {
+                body_idx: self.inline_literals(body_idx, literal),
+            },
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => Ast::TryCatch {
+                body_idx: self.inline_literals(body_idx, literal),
+                handler_idx: self.inline_literals(handler_idx, literal),
+            },
+            Ast::MacroCall {
+                call_idx,
+                expansion_idx,
+            } => Ast::MacroCall {
+                call_idx: self.copy_subtree(call_idx, 0),
+                expansion_idx: self.inline_literals(expansion_idx, literal),
+            },
+            Ast::Comptime { .. } => return self.copy_subtree(node, 0),
+            node @ (Ast::Integer(_)
+            | Ast::ParamRef { .. }
+            | Ast::PrimitiveFunc(_)
+            | Ast::UserFunc(_)) => node,
+        };
+
+        let node_idx = AstIdx(self.nodes.len());
+        self.copy_annotations(node, node_idx);
+        self.nodes.push(copied);
+        node_idx
+    }
+
+    /// Keep what is known about the node `from` for its copy `to`
+    fn copy_annotations(&mut self, from: AstIdx, to: AstIdx) {
+        if let Some(named_args) = self.named_args.get(&from).cloned() {
+            self.named_args.insert(to, named_args);
+        }
+        if let Some(&block) = self.comptime_blocks.get(&from) {
+            self.comptime_blocks.insert(to, block);
+        }
+    }
+
+    pub fn add_try(&mut self, body_idx: AstIdx) -> AstIdx {
+        let node_idx = AstIdx(self.nodes.len());
+        self.nodes.push(Ast::Try { body_idx });
+        node_idx
+    }
+
+    pub fn add_try_catch(&mut self, body_idx: AstIdx, handler_idx: AstIdx) -> AstIdx {
+        let node_idx = AstIdx(self.nodes.len());
+        self.nodes.push(Ast::TryCatch {
+            body_idx,
+            handler_idx,
+        });
+        node_idx
+    }
+
+    pub fn add_guards(
+        &mut self,
+        name_idx: NameIdx,
+        child_start: AstIdx,
+        child_count: usize,
+        len: usize,
+    ) -> AstIdx {
+        let node_idx = AstIdx(self.nodes.len());
+        self.nodes.push(Ast::Guards {
+            name_idx,
+            child_start,
+            child_count,
+            len,
+        });
+        node_idx
+    }
+
+    pub fn add_const_def(&mut self, name: &str, body_idx: AstIdx) -> AstIdx {
+        let name_idx = self.intern_string(name);
+        let node_idx = AstIdx(self.nodes.len());
+        self.nodes.push(Ast::ConstDef { name_idx, body_idx });
+
+        self.const_defs.insert(name_idx, node_idx);
+
+        node_idx
+    }
+
+    pub fn add_macro_def(&mut self, name: &str, macro_def: MacroDef) -> NameIdx {
+        let name_idx = self.intern_string(name);
+        self.macros.insert(name_idx, macro_def);
+        name_idx
+    }
+
+    pub fn add_effect_def(&mut self, name: &str, decl: EffectDecl) -> NameIdx {
+        let name_idx = self.intern_string(name);
+        self.effects.insert(name_idx, decl);
+        name_idx
+    }
+
+    pub fn add_operator_def(
+        &mut self,
+        symbol: &str,
+        fixity: Fixity,
+        precedence: u8,
+        func_name: &str,
+    ) -> NameIdx {
+        let symbol_idx = self.intern_string(symbol);
+        let func_name = self.intern_string(func_name);
+        self.operators.insert(
+            symbol_idx,
+            OperatorDef {
+                fixity,
+                precedence,
+                func_name,
+            },
+        );
+        symbol_idx
+    }
+
+    pub fn get_operator(&self, symbol: &str) -> Option<OperatorDef> {
+        let symbol_idx = self.string_map.get(symbol)?;
+        self.operators.get(symbol_idx).copied()
+    }
+
+    /// The operator written for calls of `func_name`, preferring built-in
+    /// operators and then the earliest declared one.
+    pub fn get_operator_for_func(&self, func_name: NameIdx) -> Option<(NameIdx, OperatorDef)> {
+        self.operators
+            .iter()
+            .filter(|(_, def)| def.func_name == func_name)
+            .map(|(&symbol, &def)| (symbol, def))
+            .min_by_key(|&(symbol, _)| symbol.0)
+    }
+
+    /// A reference to a named function, either a primitive or a user function
+    pub fn add_func_ref(&mut self, name: &str) -> AstIdx {
+        if let Some(prim_func) = self.get_primitive_func(name) {
+            self.add_primitive_func(prim_func)
+        } else {
+            self.add_user_func(name)
+        }
+    }
+
+    pub fn get_primitive_name(&self, func: PrimitiveFunc) -> &'static str {
+        match func {
+            PrimitiveFunc::Add => "add",
+            PrimitiveFunc::Multiply => "multiply",
+            PrimitiveFunc::Pipe => "pipe",
+            PrimitiveFunc::Compose => "compose",
+            PrimitiveFunc::Fold => "fold",
+            PrimitiveFunc::Equal => "equal",
+            PrimitiveFunc::Less => "less",
+            PrimitiveFunc::Greater => "greater",
+            PrimitiveFunc::OptionSome => "some",
+            PrimitiveFunc::OptionNone => "none",
+            PrimitiveFunc::ResultOk => "ok",
+            PrimitiveFunc::ResultErr => "err",
+            PrimitiveFunc::Raise => "raise",
+            PrimitiveFunc::ErrorMessage => "message",
+            PrimitiveFunc::ErrorPayload => "payload",
+            PrimitiveFunc::RefNew => "ref",
+            PrimitiveFunc::RefGet => "get",
+            PrimitiveFunc::RefSet => "set",
+            PrimitiveFunc::ArrayNew => "array",
+            PrimitiveFunc::ArrayIndex => "index",
+            PrimitiveFunc::Len => "len",
+            PrimitiveFunc::Slice => "slice",
+            PrimitiveFunc::Push => "push",
+            PrimitiveFunc::MapNew => "map_of",
+            PrimitiveFunc::MapInsert => "insert",
+            PrimitiveFunc::MapRemove => "remove",
+            PrimitiveFunc::MapKeys => "keys",
+            PrimitiveFunc::MapValues => "values",
+            PrimitiveFunc::MapContains => "contains",
+            PrimitiveFunc::Range => "range",
+            PrimitiveFunc::RangeInclusive => "range_inclusive",
+            PrimitiveFunc::SeqTake => "take",
+            PrimitiveFunc::SeqDrop => "drop",
+            PrimitiveFunc::SeqMap => "map",
+            PrimitiveFunc::SeqFilter => "filter",
+            PrimitiveFunc::SeqZip => "zip",
+            PrimitiveFunc::SeqCollect => "collect",
+            PrimitiveFunc::Lazy => "lazy",
+            PrimitiveFunc::Force => "force",
+            PrimitiveFunc::Yield => "yield",
+            PrimitiveFunc::SeqNext => "next",
+            PrimitiveFunc::Reset => "reset",
+            PrimitiveFunc::Shift => "shift",
+            PrimitiveFunc::CallCc => "callcc",
+            PrimitiveFunc::Perform => "perform",
+            PrimitiveFunc::Handle => "handle",
+            PrimitiveFunc::Quote => "quote",
+            PrimitiveFunc::Eval => "eval",
+            PrimitiveFunc::CodeKind => "code_kind",
+            PrimitiveFunc::CodeParts => "code_parts",
+            PrimitiveFunc::CodeCall => "code_call",
+            PrimitiveFunc::Lift => "lift",
+            PrimitiveFunc::Comptime => "comptime",
+        }
+    }
+
     pub fn get_primitive_func(&self, name: &str) -> Option<PrimitiveFunc> {
         match name {
             "add" => Some(PrimitiveFunc::Add),
             "multiply" => Some(PrimitiveFunc::Multiply),
+            "pipe" => Some(PrimitiveFunc::Pipe),
+            "compose" => Some(PrimitiveFunc::Compose),
+            "fold" => Some(PrimitiveFunc::Fold),
+            "equal" => Some(PrimitiveFunc::Equal),
+            "less" => Some(PrimitiveFunc::Less),
+            "greater" => Some(PrimitiveFunc::Greater),
+            "some" => Some(PrimitiveFunc::OptionSome),
+            "none" => Some(PrimitiveFunc::OptionNone),
+            "ok" => Some(PrimitiveFunc::ResultOk),
+            "err" => Some(PrimitiveFunc::ResultErr),
+            "raise" => Some(PrimitiveFunc::Raise),
+            "message" => Some(PrimitiveFunc::ErrorMessage),
+            "payload" => Some(PrimitiveFunc::ErrorPayload),
+            "ref" => Some(PrimitiveFunc::RefNew),
+            "get" => Some(PrimitiveFunc::RefGet),
+            "set" => Some(PrimitiveFunc::RefSet),
+            "array" => Some(PrimitiveFunc::ArrayNew),
+            "index" => Some(PrimitiveFunc::ArrayIndex),
+            "len" => Some(PrimitiveFunc::Len),
+            "slice" => Some(PrimitiveFunc::Slice),
+            "push" => Some(PrimitiveFunc::Push),
+            "map_of" => Some(PrimitiveFunc::MapNew),
+            "insert" => Some(PrimitiveFunc::MapInsert),
+            "remove" => Some(PrimitiveFunc::MapRemove),
+            "keys" => Some(PrimitiveFunc::MapKeys),
+            "values" => Some(PrimitiveFunc::MapValues),
+            "contains" => Some(PrimitiveFunc::MapContains),
+            "range" => Some(PrimitiveFunc::Range),
+            "range_inclusive" => Some(PrimitiveFunc::RangeInclusive),
+            "take" => Some(PrimitiveFunc::SeqTake),
+            "drop" => Some(PrimitiveFunc::SeqDrop),
+            "map" => Some(PrimitiveFunc::SeqMap),
+            "filter" => Some(PrimitiveFunc::SeqFilter),
+            "zip" => Some(PrimitiveFunc::SeqZip),
+            "collect" => Some(PrimitiveFunc::SeqCollect),
+            "lazy" => Some(PrimitiveFunc::Lazy),
+            "force" => Some(PrimitiveFunc::Force),
+            "yield" => Some(PrimitiveFunc::Yield),
+            "next" => Some(PrimitiveFunc::SeqNext),
+            "reset" => Some(PrimitiveFunc::Reset),
+            "shift" => Some(PrimitiveFunc::Shift),
+            "callcc" => Some(PrimitiveFunc::CallCc),
+            "perform" => Some(PrimitiveFunc::Perform),
+            "handle" => Some(PrimitiveFunc::Handle),
+            "quote" => Some(PrimitiveFunc::Quote),
+            "eval" => Some(PrimitiveFunc::Eval),
+            "code_kind" => Some(PrimitiveFunc::CodeKind),
+            "code_parts" => Some(PrimitiveFunc::CodeParts),
+            "code_call" => Some(PrimitiveFunc::CodeCall),
+            "lift" => Some(PrimitiveFunc::Lift),
+            "comptime" => Some(PrimitiveFunc::Comptime),
             _ => None,
         }
     }
@@ -173,8 +980,20 @@ impl AstPool {
     pub fn len(&self, idx: AstIdx) -> usize {
         match self[idx] {
             Ast::PrimitiveFunc(_) | Ast::UserFunc(_) | Ast::Integer(_) | Ast::ParamRef { .. } => 1,
-            Ast::Call { len, .. } => len + 1,
-            Ast::Lambda { .. } | Ast::FunctionDef { .. } => {
+            Ast::Call { len, .. } | Ast::Guards { len, .. } => len + 1,
+            Ast::MacroCall {
+                call_idx,
+                expansion_idx,
+            } => self.len(call_idx) + self.len(expansion_idx) + 1,
+            Ast::Comptime {
+                block_idx,
+                value_idx,
+            } => self.len(block_idx) + self.len(value_idx) + 1,
+            Ast::Lambda { .. }
+            | Ast::FunctionDef { .. }
+            | Ast::ConstDef { .. }
+            | Ast::Try { .. }
+            | Ast::TryCatch { .. } => {
                 1 + if let Some(children) = self.children(idx) {
                     children.iter().map(|&child| self.len(child)).sum()
                 } else {
@@ -196,6 +1015,11 @@ impl AstPool {
                 child_count,
                 child_start,
                 ..
+            }
+            | Ast::Guards {
+                child_count,
+                child_start,
+                ..
             } => {
                 if child_count > 0 {
                     let mut children = Vec::with_capacity(child_count);
@@ -217,9 +1041,18 @@ impl AstPool {
                 }
             }
 
-            Ast::FunctionDef { body_idx, .. } | Ast::Lambda { body_idx, .. } => {
-                Some(vec![body_idx])
-            }
+            Ast::FunctionDef { body_idx, .. }
+            | Ast::ConstDef { body_idx, .. }
+            | Ast::Lambda { body_idx, .. }
+            | Ast::Try { body_idx } => Some(vec![body_idx]),
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => Some(vec![body_idx, handler_idx]),
+            // The call as written is not part of the program
+            Ast::MacroCall { expansion_idx, .. } => Some(vec![expansion_idx]),
+            // Likewise the block of an evaluated `comptime`
+            Ast::Comptime { value_idx, .. } => Some(vec![value_idx]),
         }
     }
 
@@ -230,13 +1063,8 @@ impl AstPool {
         child_count: usize,
         len: usize,
     ) -> AstIdx {
-        if let Some(prim_func) = self.get_primitive_func(name) {
-            let func_idx = self.add_primitive_func(prim_func);
-            self.add_call(func_idx, child_start, child_count, len)
-        } else {
-            let func_idx = self.add_user_func(name);
-            self.add_call(func_idx, child_start, child_count, len)
-        }
+        let func_idx = self.add_func_ref(name);
+        self.add_call(func_idx, child_start, child_count, len)
     }
 
     pub fn add_lambda_call(
@@ -268,10 +1096,7 @@ impl AstPool {
                     )
                 }
                 Ast::PrimitiveFunc(func) => {
-                    let func_name = match func {
-                        PrimitiveFunc::Add => "add",
-                        PrimitiveFunc::Multiply => "multiply",
-                    };
+                    let func_name = self.get_primitive_name(*func);
                     println!(
                         "{}: PrimitiveFunction {{ func: {:?} ({}) }}",
                         i, func, func_name
@@ -299,12 +1124,13 @@ impl AstPool {
                 Ast::FunctionDef {
                     name_idx,
                     param_count,
+                    variadic,
                     body_idx,
                 } => {
                     let name = self.get_string(*name_idx);
                     println!(
-                        "{}: FunctionDef {{ name_idx: {} ({}), param_count: {}, body_idx: {} }}",
-                        i, name_idx.0, name, param_count, body_idx.0
+                        "{}: FunctionDef {{ name_idx: {} ({}), param_count: {}, variadic: {}, body_idx: {} }}",
+                        i, name_idx.0, name, param_count, variadic, body_idx.0
                     )
                 }
                 Ast::Lambda {
@@ -316,6 +1142,55 @@ impl AstPool {
                         i, param_count, body_idx.0
                     )
                 }
+                Ast::ConstDef { name_idx, body_idx } => {
+                    let name = self.get_string(*name_idx);
+                    println!(
+                        "{}: ConstDef {{ name_idx: {} ({}), body_idx: {} }}",
+                        i, name_idx.0, name, body_idx.0
+                    )
+                }
+                Ast::Try { body_idx } => {
+                    println!("{}: Try {{ body_idx: {} }}", i, body_idx.0)
+                }
+                Ast::TryCatch {
+                    body_idx,
+                    handler_idx,
+                } => {
+                    println!(
+                        "{}: TryCatch {{ body_idx: {}, handler_idx: {} }}",
+                        i, body_idx.0, handler_idx.0
+                    )
+                }
+                Ast::Guards {
+                    name_idx,
+                    child_start,
+                    child_count,
+                    len,
+                } => {
+                    let name = self.get_string(*name_idx);
+                    println!(
+                        "{}: Guards {{ name_idx: {} ({}), child_count: {}, child_start: {}, len: {} }}",
+                        i, name_idx.0, name, child_count, child_start.0, len
+                    )
+                }
+                Ast::MacroCall {
+                    call_idx,
+                    expansion_idx,
+                } => {
+                    println!(
+                        "{}: MacroCall {{ call_idx: {}, expansion_idx: {} }}",
+                        i, call_idx.0, expansion_idx.0
+                    )
+                }
+                Ast::Comptime {
+                    block_idx,
+                    value_idx,
+                } => {
+                    println!(
+                        "{}: Comptime {{ block_idx: {}, value_idx: {} }}",
+                        i, block_idx.0, value_idx.0
+                    )
+                }
             }
         }
 
@@ -324,6 +1199,23 @@ impl AstPool {
             println!("{}: {}", i, s);
         }
 
+        println!("\nConstant Definitions:");
+        for (&name_idx, &node_idx) in &self.const_defs {
+            let name = self.get_string(name_idx);
+            println!("{} ({}) -> node {}", name, name_idx.0, node_idx.0);
+        }
+
+        println!("\nOperators:");
+        for (&symbol_idx, def) in &self.operators {
+            println!(
+                "{} {} {} = {}",
+                def.fixity.keyword(),
+                def.precedence,
+                self.get_string(symbol_idx),
+                self.get_string(def.func_name)
+            );
+        }
+
         println!("\nFunction Definitions:");
         for (&name_idx, &node_idx) in &self.function_defs {
             let name = self.get_string(name_idx);
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::ast::attributes::AttributeArg;
 use crate::ast::indices::{AstIdx, NameIdx};
+use crate::ast::operators::{is_builtin_operator, Fixity, OperatorDef};
 use crate::ast::pool::AstPool;
 use crate::ast::primitives::PrimitiveFunc;
 
@@ -14,6 +16,8 @@ pub struct PrintConfig {
     pub spaces_around_operators: bool,
     pub newlines_after_functions: bool,
     pub max_line_length: usize,
+    /// Print the code macro calls expand to rather than the calls
+    pub expand_macros: bool,
 }
 
 impl Default for PrintConfig {
@@ -24,6 +28,7 @@ impl Default for PrintConfig {
             spaces_around_operators: true,
             newlines_after_functions: true,
             max_line_length: 80,
+            expand_macros: false,
         }
     }
 }
@@ -36,6 +41,9 @@ pub struct PrettyPrinter<'a> {
     // Track the current function context during traversal
     current_function: Option<NameIdx>,
     current_level: usize,
+    // Names of the `where` bindings visible in the body being printed, by
+    // (level, offset)
+    local_names: HashMap<(usize, usize), NameIdx>,
 }
 
 impl<'a> PrettyPrinter<'a> {
@@ -46,6 +54,7 @@ impl<'a> PrettyPrinter<'a> {
             param_names: HashMap::new(),
             current_function: None,
             current_level: 0,
+            local_names: HashMap::new(),
         }
     }
 
@@ -56,6 +65,7 @@ impl<'a> PrettyPrinter<'a> {
             param_names: HashMap::new(),
             current_function: None,
             current_level: 0,
+            local_names: HashMap::new(),
         }
     }
 
@@ -67,6 +77,7 @@ impl<'a> PrettyPrinter<'a> {
             param_names: HashMap::new(),
             current_function: None,
             current_level: 0,
+            local_names: HashMap::new(),
         };
 
         // First pass - collect parameter names
@@ -85,12 +96,42 @@ impl<'a> PrettyPrinter<'a> {
             param_names: HashMap::new(),
             current_function: None,
             current_level: 0,
+            local_names: HashMap::new(),
         };
 
         // First pass - collect parameter names from all functions
         for (_, &node_idx) in &self.pool.function_defs {
             printer.collect_param_names(node_idx);
         }
+        for &node_idx in self.pool.const_defs.values() {
+            printer.collect_param_names(node_idx);
+        }
+
+        let operators = self.print_operator_defs(|_| true);
+        if !operators.is_empty() {
+            output.push_str(&operators);
+            output.push('\n');
+        }
+
+        let effects = self.print_effect_defs();
+        if !effects.is_empty() {
+            output.push_str(&effects);
+            output.push('\n');
+        }
+
+        let macros = self.print_macro_defs();
+        if !macros.is_empty() {
+            output.push_str(&macros);
+            output.push('\n');
+        }
+
+        if !self.pool.const_defs.is_empty() {
+            for &node_idx in self.pool.const_defs.values() {
+                printer.print_node_to_string(node_idx, 0, &mut output);
+                output.push('\n');
+            }
+            output.push('\n');
+        }
 
         // Second pass - print all functions with collected parameter names
         for (&_name_idx, &node_idx) in &self.pool.function_defs {
@@ -105,6 +146,142 @@ impl<'a> PrettyPrinter<'a> {
         output
     }
 
+    /// Declarations of the user-defined operators whose function is
+    /// accepted by `filter`, one per line and sorted by symbol
+    pub fn print_operator_defs(&self, filter: impl Fn(NameIdx) -> bool) -> String {
+        let mut operators: Vec<_> = self
+            .pool
+            .operators
+            .iter()
+            .map(|(&symbol_idx, def)| (self.pool.get_string(symbol_idx), def))
+            .filter(|&(symbol, def)| !is_builtin_operator(symbol) && filter(def.func_name))
+            .collect();
+        operators.sort_by_key(|&(symbol, _)| symbol);
+
+        let mut output = String::new();
+        for (symbol, def) in operators {
+            output.push_str(&format!(
+                "{} {} {} = {}\n",
+                def.fixity.keyword(),
+                def.precedence,
+                symbol,
+                self.pool.get_string(def.func_name)
+            ));
+        }
+        output
+    }
+
+    /// Declarations of the effects, one per line and sorted by name
+    pub fn print_effect_defs(&self) -> String {
+        let mut effects: Vec<_> = self
+            .pool
+            .effects
+            .iter()
+            .map(|(&name_idx, decl)| (self.pool.get_string(name_idx), decl))
+            .collect();
+        effects.sort_by_key(|&(name, _)| name);
+
+        let mut output = String::new();
+        for (name, decl) in effects {
+            let params: Vec<&str> = decl
+                .params
+                .iter()
+                .map(|&param| self.pool.get_string(param))
+                .collect();
+            output.push_str(&format!(
+                "effect {} : ({}) -> {}\n",
+                name,
+                params.join(", "),
+                self.pool.get_string(decl.result)
+            ));
+        }
+        output
+    }
+
+    /// Definitions of the macros, one per line and sorted by name
+    pub fn print_macro_defs(&self) -> String {
+        let mut macros: Vec<_> = self
+            .pool
+            .macros
+            .iter()
+            .map(|(&name_idx, def)| (name_idx, self.pool.get_string(name_idx), def))
+            .collect();
+        macros.sort_by_key(|&(_, name, _)| name);
+
+        let mut output = String::new();
+        for (name_idx, name, def) in macros {
+            let params: Vec<&str> = def
+                .params
+                .iter()
+                .map(|&param| self.pool.get_string(param))
+                .collect();
+            output.push_str(&format!(
+                "macro {}({}) {{ {} }}\n",
+                name,
+                params.join(", "),
+                self.print_template(name_idx, &def.params, def.body_idx)
+            ));
+        }
+        output
+    }
+
+    /// Print an expression like the body of a function `owner` taking
+    /// `params`, with their names
+    pub fn print_template(&self, owner: NameIdx, params: &[NameIdx], body_idx: AstIdx) -> String {
+        let mut printer = Self::with_config(self.pool, self.config.clone());
+        printer.current_function = Some(owner);
+        printer.current_level = 1;
+        printer.collect_param_names(body_idx);
+        for (offset, &param) in params.iter().enumerate() {
+            printer.local_names.insert((1, offset), param);
+        }
+
+        let mut output = String::new();
+        printer.print_node_to_string(body_idx, 0, &mut output);
+        output
+    }
+
+    /// The doc comment of a function, one `///` line per line of text
+    fn print_doc(&self, name_idx: NameIdx, indent: &str, output: &mut String) {
+        for line in self
+            .pool
+            .docs
+            .get(&name_idx)
+            .into_iter()
+            .flat_map(|doc| doc.lines())
+        {
+            output.push_str(indent);
+            output.push_str("///");
+            if !line.is_empty() {
+                output.push(' ');
+                output.push_str(line);
+            }
+            output.push('\n');
+        }
+    }
+
+    /// The attributes of a function as written, one per line
+    fn print_attributes(&self, name_idx: NameIdx, indent: &str, output: &mut String) {
+        for attribute in self.pool.attributes.get(&name_idx).into_iter().flatten() {
+            output.push_str(indent);
+            output.push_str("#[");
+            output.push_str(self.pool.get_string(attribute.name));
+            if !attribute.args.is_empty() {
+                let args: Vec<String> = attribute
+                    .args
+                    .iter()
+                    .map(|arg| match arg {
+                        AttributeArg::Int(i) => i.to_string(),
+                        AttributeArg::Str(s) => format!("\"{}\"", s),
+                        AttributeArg::Name(name) => self.pool.get_string(*name).to_string(),
+                    })
+                    .collect();
+                output.push_str(&format!("({})", args.join(", ")));
+            }
+            output.push_str("]\n");
+        }
+    }
+
     // Collect parameter names from function and lambda definitions
     fn collect_param_names(&mut self, node_idx: AstIdx) {
         match self.pool[node_idx] {
@@ -112,17 +289,36 @@ impl<'a> PrettyPrinter<'a> {
                 name_idx,
                 param_count,
                 body_idx,
+                ..
             } => {
                 let prev_function = self.current_function;
                 let prev_level = self.current_level;
 
                 self.current_function = Some(name_idx);
-                self.current_level = 0;
+                self.current_level = 1;
 
-                // Add default parameter names for this function
+                // Parameters keep their declared names, calls with named
+                // arguments refer to them
+                let rest = self.pool.rest_params.get(&name_idx).copied();
+                let kept_names: Vec<String> = self
+                    .pool
+                    .function_params
+                    .get(&name_idx)
+                    .into_iter()
+                    .flatten()
+                    .map(|param| param.name)
+                    .chain(rest)
+                    .map(|name| self.pool.get_string(name).to_string())
+                    .collect();
                 for i in 0..param_count {
-                    let param_key = (name_idx, 0, i);
-                    let param_name = format!("p{}", i);
+                    let param_key = (name_idx, 1, i);
+                    let param_name = kept_names.get(i).cloned().unwrap_or_else(|| {
+                        let mut name = format!("p{}", i);
+                        while kept_names.contains(&name) {
+                            name.push('_');
+                        }
+                        name
+                    });
                     self.param_names.insert(param_key, param_name);
                 }
 
@@ -133,6 +329,17 @@ impl<'a> PrettyPrinter<'a> {
                 self.current_function = prev_function;
                 self.current_level = prev_level;
             }
+            Ast::ConstDef { name_idx, body_idx } => {
+                let prev_function = self.current_function;
+                let prev_level = self.current_level;
+
+                self.current_function = Some(name_idx);
+                self.current_level = 1;
+                self.collect_param_names(body_idx);
+
+                self.current_function = prev_function;
+                self.current_level = prev_level;
+            }
             Ast::Lambda {
                 param_count,
                 body_idx,
@@ -154,9 +361,11 @@ impl<'a> PrettyPrinter<'a> {
                     self.current_level = prev_level;
                 }
             }
-            Ast::Call { func_idx, .. } => {
+            Ast::Call { .. } | Ast::Guards { .. } => {
                 // Traverse function and arguments
-                self.collect_param_names(func_idx);
+                if let Ast::Call { func_idx, .. } = self.pool[node_idx] {
+                    self.collect_param_names(func_idx);
+                }
 
                 if let Some(children) = self.pool.children(node_idx) {
                     for child_idx in children {
@@ -164,12 +373,32 @@ impl<'a> PrettyPrinter<'a> {
                     }
                 }
             }
+            Ast::Try { body_idx } => self.collect_param_names(body_idx),
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => {
+                self.collect_param_names(body_idx);
+                self.collect_param_names(handler_idx);
+            }
+            Ast::MacroCall {
+                call_idx,
+                expansion_idx,
+            } => {
+                self.collect_param_names(call_idx);
+                self.collect_param_names(expansion_idx);
+            }
+            Ast::Comptime { block_idx, .. } => self.collect_param_names(block_idx),
             // No need to handle other cases as they don't define parameters
             _ => {}
         }
     }
 
     fn get_param_name(&self, name_idx: NameIdx, level: usize, offset: usize) -> String {
+        if let Some(&local_name) = self.local_names.get(&(level, offset)) {
+            return self.pool.get_string(local_name).to_string();
+        }
+
         // Try to find the parameter name in our mapping
         if let Some(current_func) = self.current_function {
             let param_key = (current_func, level, offset);
@@ -179,7 +408,7 @@ impl<'a> PrettyPrinter<'a> {
         }
 
         // Fallback to a generic parameter name based on level and offset
-        if level == 0 {
+        if level == 1 {
             format!("p{}", offset)
         } else {
             format!("l{}p{}", level, offset)
@@ -213,12 +442,14 @@ impl<'a> PrettyPrinter<'a> {
                 }
             }
 
-            Ast::PrimitiveFunc(func) => {
-                output.push_str(match func {
-                    PrimitiveFunc::Add => "add",
-                    PrimitiveFunc::Multiply => "multiply",
-                });
-            }
+            Ast::PrimitiveFunc(func) => match self.operator_of(node_idx) {
+                Some((symbol, _)) => {
+                    output.push('(');
+                    output.push_str(symbol);
+                    output.push(')');
+                }
+                None => output.push_str(self.pool.get_primitive_name(func)),
+            },
 
             Ast::UserFunc(name_idx) => {
                 let func_name = self.pool.get_string(name_idx);
@@ -229,6 +460,15 @@ impl<'a> PrettyPrinter<'a> {
                 param_count,
                 body_idx,
             } => {
+                if let Some((symbol, operand)) = self.right_section(param_count, body_idx) {
+                    output.push('(');
+                    output.push_str(symbol);
+                    output.push(' ');
+                    self.print_node_to_string(operand, indent_level, output);
+                    output.push(')');
+                    return;
+                }
+
                 output.push_str("lambda ");
 
                 // Add parameter names based on current context and level
@@ -269,69 +509,204 @@ impl<'a> PrettyPrinter<'a> {
             Ast::Call { func_idx, .. } => {
                 let children = self.pool.children(node_idx).unwrap_or_default();
 
-                if let Ast::PrimitiveFunc(func) = self.pool[func_idx] {
-                    {
-                        if children.len() != 2 {
-                            // For non-binary primitive calls, use function call syntax
-                            output.push_str(match func {
-                                PrimitiveFunc::Add => "add",
-                                PrimitiveFunc::Multiply => "multiply",
-                            });
-                            output.push('(');
-
-                            for (i, &child) in children.iter().enumerate() {
-                                if i > 0 {
-                                    output.push_str(", ");
-                                }
-                                self.print_node_to_string(child, indent_level, output);
-                            }
-
-                            output.push(')');
-                        } else {
-                            output.push('(');
+                // `lazy e` is `lazy` applied to a lambda without parameters
+                if let (Ast::PrimitiveFunc(PrimitiveFunc::Lazy), &[lambda_idx]) =
+                    (self.pool[func_idx], children.as_slice())
+                    && let Ast::Lambda {
+                        param_count: 0,
+                        body_idx,
+                    } = self.pool[lambda_idx]
+                {
+                    output.push_str("lazy ");
+                    let prev_level = self.current_level;
+                    self.current_level += 1;
+                    self.print_node_to_string(body_idx, indent_level, output);
+                    self.current_level = prev_level;
+                    return;
+                }
 
-                            self.print_node_to_string(children[0], indent_level, output);
-
-                            output.push_str(match func {
-                                PrimitiveFunc::Add => {
-                                    if self.config.spaces_around_operators {
-                                        " + "
-                                    } else {
-                                        "+"
-                                    }
-                                }
-                                PrimitiveFunc::Multiply => {
-                                    if self.config.spaces_around_operators {
-                                        " * "
-                                    } else {
-                                        "*"
-                                    }
-                                }
-                            });
+                // `quote { e }` and `comptime { e }` keep `e` in a lambda
+                // like `lazy e`
+                if let (
+                    Ast::PrimitiveFunc(func @ (PrimitiveFunc::Quote | PrimitiveFunc::Comptime)),
+                    &[lambda_idx],
+                ) = (self.pool[func_idx], children.as_slice())
+                    && let Ast::Lambda {
+                        param_count: 0,
+                        body_idx,
+                    } = self.pool[lambda_idx]
+                {
+                    output.push_str(self.pool.get_primitive_name(func));
+                    output.push_str(" { ");
+                    let prev_level = self.current_level;
+                    self.current_level += 1;
+                    self.print_node_to_string(body_idx, indent_level, output);
+                    self.current_level = prev_level;
+                    output.push_str(" }");
+                    return;
+                }
 
-                            self.print_node_to_string(children[1], indent_level, output);
+                // `yield e; rest` continues with a lambda computing the rest
+                if let (Ast::PrimitiveFunc(PrimitiveFunc::Yield), &[value_idx, lambda_idx]) =
+                    (self.pool[func_idx], children.as_slice())
+                    && let Ast::Lambda {
+                        param_count: 0,
+                        body_idx,
+                    } = self.pool[lambda_idx]
+                {
+                    output.push_str("yield ");
+                    self.print_node_to_string(value_idx, indent_level, output);
 
-                            output.push(')');
-                        }
+                    let ends = match self.pool[body_idx] {
+                        Ast::Call {
+                            func_idx,
+                            child_count: 0,
+                            ..
+                        } => matches!(
+                            self.pool[func_idx],
+                            Ast::PrimitiveFunc(PrimitiveFunc::ArrayNew)
+                        ),
+                        _ => false,
+                    };
+                    if !ends {
+                        output.push_str("; ");
+                        let prev_level = self.current_level;
+                        self.current_level += 1;
+                        self.print_node_to_string(body_idx, indent_level, output);
+                        self.current_level = prev_level;
                     }
-                } else {
-                    self.print_node_to_string(func_idx, indent_level, output);
-                    output.push('(');
+                    return;
+                }
 
-                    for (i, &child) in children.iter().enumerate() {
+                // `perform Ask(x)` gives the effect first
+                if let (Ast::PrimitiveFunc(PrimitiveFunc::Perform), Some((&effect, args))) =
+                    (self.pool[func_idx], children.split_first())
+                {
+                    output.push_str("perform ");
+                    self.print_node_to_string(effect, indent_level, output);
+                    output.push('(');
+                    for (i, &arg) in args.iter().enumerate() {
                         if i > 0 {
                             output.push_str(", ");
                         }
-                        self.print_node_to_string(child, indent_level, output);
+                        self.print_node_to_string(arg, indent_level, output);
                     }
-
                     output.push(')');
+                    return;
+                }
+
+                // `handle e with { .. }` computes `e` in a lambda, followed
+                // by each effect and a lambda handling it
+                if let (Ast::PrimitiveFunc(PrimitiveFunc::Handle), Some((&body, clauses))) =
+                    (self.pool[func_idx], children.split_first())
+                    && let Ast::Lambda {
+                        param_count: 0,
+                        body_idx,
+                    } = self.pool[body]
+                {
+                    self.print_handle(body_idx, clauses, indent_level, output);
+                    return;
+                }
+
+                match (self.operator_of(func_idx), children.as_slice()) {
+                    (Some((symbol, def)), &[left, right]) => {
+                        self.print_operand(left, def, Fixity::Left, indent_level, output);
+                        if self.config.spaces_around_operators {
+                            output.push(' ');
+                            output.push_str(symbol);
+                            output.push(' ');
+                        } else {
+                            output.push_str(symbol);
+                        }
+                        self.print_operand(right, def, Fixity::Right, indent_level, output);
+                    }
+                    (Some((symbol, _)), &[operand]) => {
+                        // Left section
+                        output.push('(');
+                        self.print_node_to_string(operand, indent_level, output);
+                        output.push(' ');
+                        output.push_str(symbol);
+                        output.push(')');
+                    }
+                    (None, &[array, index])
+                        if matches!(
+                            self.pool[func_idx],
+                            Ast::PrimitiveFunc(PrimitiveFunc::ArrayIndex)
+                        ) =>
+                    {
+                        self.print_postfix_operand(array, indent_level, output);
+                        output.push('[');
+                        self.print_node_to_string(index, indent_level, output);
+                        output.push(']');
+                    }
+                    (None, items)
+                        if matches!(
+                            self.pool[func_idx],
+                            Ast::PrimitiveFunc(PrimitiveFunc::ArrayNew)
+                        ) =>
+                    {
+                        output.push_str("#[");
+                        for (i, &item) in items.iter().enumerate() {
+                            if i > 0 {
+                                output.push_str(", ");
+                            }
+                            self.print_node_to_string(item, indent_level, output);
+                        }
+                        output.push(']');
+                    }
+                    (None, items)
+                        if matches!(
+                            self.pool[func_idx],
+                            Ast::PrimitiveFunc(PrimitiveFunc::MapNew)
+                        ) && items.len() % 2 == 0 =>
+                    {
+                        output.push_str("#{");
+                        for (i, entry) in items.chunks(2).enumerate() {
+                            if i > 0 {
+                                output.push_str(", ");
+                            }
+                            self.print_node_to_string(entry[0], indent_level, output);
+                            output.push_str(": ");
+                            self.print_node_to_string(entry[1], indent_level, output);
+                        }
+                        output.push('}');
+                    }
+                    _ => {
+                        if let Ast::PrimitiveFunc(func) = self.pool[func_idx] {
+                            output.push_str(self.pool.get_primitive_name(func));
+                        } else if self.is_plain_callee(func_idx) {
+                            self.print_node_to_string(func_idx, indent_level, output);
+                        } else {
+                            output.push('(');
+                            self.print_node_to_string(func_idx, indent_level, output);
+                            output.push(')');
+                        }
+                        output.push('(');
+
+                        let named_args = self.pool.named_args.get(&node_idx);
+                        for (i, &child) in children.iter().enumerate() {
+                            if i > 0 {
+                                output.push_str(", ");
+                            }
+                            let name = named_args.and_then(|named_args| {
+                                named_args.names.get(i.checked_sub(named_args.positional)?)
+                            });
+                            if let Some(&name) = name {
+                                output.push_str(self.pool.get_string(name));
+                                output.push_str(": ");
+                            }
+                            self.print_node_to_string(child, indent_level, output);
+                        }
+
+                        output.push(')');
+                    }
                 }
             }
 
             Ast::FunctionDef {
                 name_idx,
                 param_count,
+                variadic,
                 body_idx,
             } => {
                 // Store previous function context
@@ -340,9 +715,11 @@ impl<'a> PrettyPrinter<'a> {
 
                 // Set current function context for parameter name lookup
                 self.current_function = Some(name_idx);
-                self.current_level = 0;
+                self.current_level = 1;
 
                 // Function header
+                self.print_doc(name_idx, &indent, output);
+                self.print_attributes(name_idx, &indent, output);
                 output.push_str(&indent);
                 output.push_str("fn ");
                 output.push_str(self.pool.get_string(name_idx));
@@ -353,32 +730,426 @@ impl<'a> PrettyPrinter<'a> {
                     if i > 0 {
                         output.push_str(", ");
                     }
-                    let param_name = self.get_param_name(name_idx, 0, i);
+                    if variadic && i + 1 == param_count {
+                        output.push_str("...");
+                    }
+                    let param_name = self.get_param_name(name_idx, 1, i);
                     output.push_str(&param_name);
+
+                    let params = self.pool.function_params.get(&name_idx);
+                    if let Some(default) = params.and_then(|params| params.get(i)?.default) {
+                        output.push_str(" = ");
+                        self.print_node_to_string(default, indent_level, output);
+                    }
                 }
 
-                output.push_str(") {\n");
+                // Guarded clauses follow the parameters, each on its own line
+                let where_clause = self.where_clause(name_idx, body_idx);
+                let inner_body = where_clause.as_ref().map_or(body_idx, |(inner, ..)| *inner);
+                let guarded = matches!(self.pool[inner_body], Ast::Guards { .. });
+                output.push_str(if guarded { ")" } else { ") {\n" });
 
                 // Function body
                 let body_indent = indent_level + 1;
-                output.push_str(&if self.config.indent_is_tab {
-                    "\t".repeat(body_indent)
-                } else {
-                    " ".repeat(body_indent * self.config.indent_size)
-                });
+                if !guarded {
+                    output.push_str(&if self.config.indent_is_tab {
+                        "\t".repeat(body_indent)
+                    } else {
+                        " ".repeat(body_indent * self.config.indent_size)
+                    });
+                }
 
-                // Print the body expression
-                self.print_node_to_string(body_idx, body_indent, output);
+                // Print the body expression, without the lambda that binds
+                // the `where` clause
+                match &where_clause {
+                    Some((inner_body, names, _)) => {
+                        for (i, &local_name) in names.iter().enumerate() {
+                            self.local_names.insert((2 + i, 0), local_name);
+                        }
+                        self.current_level = 1 + names.len();
+                        self.print_node_to_string(*inner_body, body_indent, output);
+                        self.current_level = 1;
+                        self.local_names.clear();
+                    }
+                    None => self.print_node_to_string(body_idx, body_indent, output),
+                }
 
                 // Close the function
-                output.push('\n');
-                output.push_str(&indent);
-                output.push('}');
+                if !guarded {
+                    output.push('\n');
+                    output.push_str(&indent);
+                    output.push('}');
+                }
+
+                // Each binding can use the ones before it
+                if let Some((_, names, bindings)) = where_clause {
+                    output.push_str(" where ");
+                    for (i, (&local_name, binding)) in names.iter().zip(bindings).enumerate() {
+                        if i > 0 {
+                            output.push_str(", ");
+                        }
+                        output.push_str(self.pool.get_string(local_name));
+                        self.current_level = 1 + i;
+                        self.print_where_binding(name_idx, binding, indent_level, output);
+                        self.local_names.insert((2 + i, 0), local_name);
+                    }
+                    self.current_level = 1;
+                    self.local_names.clear();
+                }
 
                 // Restore previous function context
                 self.current_function = prev_function;
                 self.current_level = prev_level;
             }
+
+            Ast::MacroCall { .. } | Ast::Comptime { .. } => {
+                self.print_node_to_string(self.shown(node_idx), indent_level, output);
+            }
+
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => {
+                output.push_str("try { ");
+                self.print_node_to_string(body_idx, indent_level, output);
+                output.push_str(" } catch ");
+
+                if let Ast::Lambda {
+                    body_idx: handler_body,
+                    ..
+                } = self.pool[handler_idx]
+                {
+                    let handler_level = self.current_level + 1;
+                    let error_name = match self.current_function {
+                        Some(func_idx) => self.get_param_name(func_idx, handler_level, 0),
+                        None => "p0".to_string(),
+                    };
+                    output.push_str(&error_name);
+                    output.push_str(" { ");
+
+                    let prev_level = self.current_level;
+                    self.current_level = handler_level;
+                    self.print_node_to_string(handler_body, indent_level + 1, output);
+                    self.current_level = prev_level;
+                    output.push_str(" }");
+                }
+            }
+
+            Ast::Try { body_idx } => {
+                self.print_postfix_operand(body_idx, indent_level, output);
+                output.push('?');
+            }
+
+            Ast::Guards { .. } => {
+                let clauses = self.pool.children(node_idx).unwrap_or_default();
+                for clause in clauses.chunks(2) {
+                    output.push('\n');
+                    output.push_str(&indent);
+                    output.push_str("| ");
+                    match *clause {
+                        [condition, value] => {
+                            self.print_node_to_string(condition, indent_level, output);
+                            output.push_str(" = ");
+                            self.print_node_to_string(value, indent_level, output);
+                        }
+                        [value] => {
+                            output.push_str("_ = ");
+                            self.print_node_to_string(value, indent_level, output);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            Ast::ConstDef { name_idx, body_idx } => {
+                let prev_function = self.current_function;
+                let prev_level = self.current_level;
+
+                // Constant bodies are scoped like a function without parameters
+                self.current_function = Some(name_idx);
+                self.current_level = 1;
+
+                output.push_str(&indent);
+                output.push_str("const ");
+                output.push_str(self.pool.get_string(name_idx));
+                output.push_str(" = ");
+                self.print_node_to_string(body_idx, indent_level, output);
+
+                self.current_function = prev_function;
+                self.current_level = prev_level;
+            }
+        }
+    }
+
+    /// The body and bindings of a function written with a `where` clause
+    fn where_clause(
+        &self,
+        name_idx: NameIdx,
+        body_idx: AstIdx,
+    ) -> Option<(AstIdx, &'a [NameIdx], Vec<AstIdx>)> {
+        let pool = self.pool;
+        let names = pool.where_bindings.get(&name_idx)?;
+        let mut inner_body = body_idx;
+        let mut bindings = Vec::with_capacity(names.len());
+        for _ in names {
+            let Ast::Call { func_idx, .. } = pool[inner_body] else {
+                return None;
+            };
+            let Ast::Lambda { body_idx, .. } = pool[func_idx] else {
+                return None;
+            };
+            bindings.extend(pool.children(inner_body).unwrap_or_default());
+            inner_body = body_idx;
+        }
+        Some((inner_body, names, bindings))
+    }
+
+    /// A `where` binding after its name: helpers as `(params) { body }`,
+    /// values as ` = value`
+    fn print_where_binding(
+        &mut self,
+        func_name: NameIdx,
+        binding: AstIdx,
+        indent_level: usize,
+        output: &mut String,
+    ) {
+        match self.pool[binding] {
+            Ast::Lambda {
+                param_count,
+                body_idx,
+            } => {
+                let level = self.current_level;
+                output.push('(');
+                for i in 0..param_count {
+                    if i > 0 {
+                        output.push_str(", ");
+                    }
+                    output.push_str(&self.get_param_name(func_name, level + 1, i));
+                }
+                output.push_str(") { ");
+                self.current_level = level + 1;
+                self.print_node_to_string(body_idx, indent_level + 1, output);
+                self.current_level = level;
+                output.push_str(" }");
+            }
+            _ => {
+                output.push_str(" = ");
+                self.print_node_to_string(binding, indent_level, output);
+            }
+        }
+    }
+
+    /// The infix operator written for calls of the given function node
+    fn print_handle(
+        &mut self,
+        body_idx: AstIdx,
+        clauses: &[AstIdx],
+        indent_level: usize,
+        output: &mut String,
+    ) {
+        let prev_level = self.current_level;
+        self.current_level += 1;
+
+        output.push_str("handle ");
+        self.print_node_to_string(body_idx, indent_level, output);
+        output.push_str(" with { ");
+
+        for (i, clause) in clauses.chunks(2).enumerate() {
+            let &[effect, handler] = clause else {
+                continue;
+            };
+            let Ast::Lambda {
+                param_count,
+                body_idx,
+            } = self.pool[handler]
+            else {
+                continue;
+            };
+            if i > 0 {
+                output.push_str(", ");
+            }
+
+            // The handler takes the arguments of the effect, then the
+            // continuation
+            let params: Vec<String> = (0..param_count)
+                .map(|offset| match self.current_function {
+                    Some(func_idx) => self.get_param_name(func_idx, self.current_level, offset),
+                    None => format!("p{}", offset),
+                })
+                .collect();
+            self.print_node_to_string(effect, indent_level, output);
+            output.push('(');
+            output.push_str(&params[..param_count.saturating_sub(1)].join(", "));
+            output.push_str(") ");
+            output.push_str(params.last().map_or("k", String::as_str));
+            output.push_str(" => ");
+            self.print_node_to_string(body_idx, indent_level, output);
+        }
+
+        output.push_str(" }");
+        self.current_level = prev_level;
+    }
+
+    fn operator_of(&self, func_idx: AstIdx) -> Option<(&'a str, OperatorDef)> {
+        let pool = self.pool;
+        let func_name = match pool[func_idx] {
+            Ast::PrimitiveFunc(func) => {
+                pool.get_name_idx_from_func(pool.get_primitive_name(func))?
+            }
+            Ast::UserFunc(name_idx) => name_idx,
+            _ => return None,
+        };
+        let (symbol_idx, def) = pool.get_operator_for_func(func_name)?;
+        Some((pool.get_string(symbol_idx), def))
+    }
+
+    /// Print the operand of a postfix `?` or `[i]`, adding parentheses when
+    /// it is an infix call
+    /// The node printed for `node_idx`, which is the call or the expansion
+    /// of a macro call depending on the configuration
+    fn shown(&self, node_idx: AstIdx) -> AstIdx {
+        match self.pool[node_idx] {
+            Ast::MacroCall {
+                call_idx,
+                expansion_idx,
+            } => self.shown(if self.config.expand_macros {
+                expansion_idx
+            } else {
+                call_idx
+            }),
+            // Blocks are printed as written, their value is not source
+            Ast::Comptime { block_idx, .. } => block_idx,
+            _ => node_idx,
+        }
+    }
+
+    /// Whether arguments can follow a callee without parentheses around it,
+    /// which infix calls and `lazy` and `yield` would take
+    fn is_plain_callee(&self, node_idx: AstIdx) -> bool {
+        let node_idx = self.shown(node_idx);
+        let Ast::Call { func_idx, .. } = self.pool[node_idx] else {
+            return true;
+        };
+        let infix = self.operator_of(func_idx).is_some()
+            && self.pool.children(node_idx).is_some_and(|c| c.len() == 2);
+        !infix && !self.is_open_ended(node_idx)
+    }
+
+    /// Whether the node is written `lazy e` or `yield e; rest`, which take
+    /// everything after them into `e` or `rest`
+    fn is_open_ended(&self, node_idx: AstIdx) -> bool {
+        match self.pool[self.shown(node_idx)] {
+            Ast::Call { func_idx, .. } => matches!(
+                self.pool[func_idx],
+                Ast::PrimitiveFunc(PrimitiveFunc::Lazy | PrimitiveFunc::Yield)
+            ),
+            _ => false,
+        }
+    }
+
+    fn print_postfix_operand(
+        &mut self,
+        node_idx: AstIdx,
+        indent_level: usize,
+        output: &mut String,
+    ) {
+        let needs_parens = !self.is_plain_callee(node_idx);
+        if needs_parens {
+            output.push('(');
+        }
+        self.print_node_to_string(node_idx, indent_level, output);
+        if needs_parens {
+            output.push(')');
+        }
+    }
+
+    /// Print an operand of an infix operator, adding parentheses when the
+    /// operand is itself an infix call that would otherwise regroup, or
+    /// would take the rest of the expression.
+    fn print_operand(
+        &mut self,
+        node_idx: AstIdx,
+        parent: OperatorDef,
+        side: Fixity,
+        indent_level: usize,
+        output: &mut String,
+    ) {
+        let regroups = match self.pool[self.shown(node_idx)] {
+            Ast::Call {
+                func_idx,
+                child_count: 2,
+                ..
+            } => match self.operator_of(func_idx) {
+                Some((_, def)) => {
+                    def.precedence < parent.precedence
+                        || (def.precedence == parent.precedence
+                            && !(def.fixity == parent.fixity && parent.fixity == side))
+                }
+                None => false,
+            },
+            _ => false,
+        };
+        let needs_parens = regroups || self.is_open_ended(node_idx);
+
+        if needs_parens {
+            output.push('(');
+            self.print_node_to_string(node_idx, indent_level, output);
+            output.push(')');
+        } else {
+            self.print_node_to_string(node_idx, indent_level, output);
+        }
+    }
+
+    /// Recognise `lambda x { x op e }` where `e` doesn't use `x`, which is
+    /// how the parser represents the right section `(op e)`.
+    fn right_section(&self, param_count: usize, body_idx: AstIdx) -> Option<(&'a str, AstIdx)> {
+        let Ast::Call { func_idx, .. } = self.pool[body_idx] else {
+            return None;
+        };
+        let (symbol, _) = self.operator_of(func_idx)?;
+        let children = self.pool.children(body_idx)?;
+        let lambda_level = self.current_level + 1;
+
+        match (param_count, children.as_slice()) {
+            (1, &[param, operand])
+                if matches!(self.pool[param], Ast::ParamRef { level, offset, .. }
+                    if level == lambda_level && offset.0 == 0)
+                    && !self.uses_level(operand, lambda_level) =>
+            {
+                Some((symbol, operand))
+            }
+            _ => None,
+        }
+    }
+
+    fn uses_level(&self, node_idx: AstIdx, level: usize) -> bool {
+        match self.pool[node_idx] {
+            Ast::ParamRef { level: l, .. } => l == level,
+            Ast::Call { func_idx, .. } => {
+                self.uses_level(func_idx, level)
+                    || self
+                        .pool
+                        .children(node_idx)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .any(|child| self.uses_level(child, level))
+            }
+            Ast::Lambda { body_idx, .. } | Ast::Try { body_idx } => {
+                self.uses_level(body_idx, level)
+            }
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => self.uses_level(body_idx, level) || self.uses_level(handler_idx, level),
+            Ast::MacroCall { expansion_idx, .. } => self.uses_level(expansion_idx, level),
+            Ast::Comptime { block_idx, .. } => self.uses_level(block_idx, level),
+            Ast::Guards { .. } => self
+                .pool
+                .children(node_idx)
+                .unwrap_or_default()
+                .into_iter()
+                .any(|child| self.uses_level(child, level)),
+            _ => false,
         }
     }
 }
@@ -2,6 +2,89 @@
 pub enum PrimitiveFunc {
     Add,
     Multiply,
+    Pipe,
+    Compose,
+    /// `fold(items, init, f)` combines the items of a list, an array or a
+    /// sequence from the left
+    Fold,
+    Equal,
+    Less,
+    Greater,
+    /// `some(x)`, `none`, `ok(x)` and `err(e)`; `none` is a value rather
+    /// than a function
+    OptionSome,
+    OptionNone,
+    ResultOk,
+    ResultErr,
+    /// `raise(value)` fails with `value` as the payload, `message(error)`
+    /// and `payload(error)` inspect a caught error
+    Raise,
+    ErrorMessage,
+    ErrorPayload,
+    /// `ref(v)` makes a mutable cell, `get(r)` reads it and `set(r, v)`
+    /// overwrites it
+    RefNew,
+    RefGet,
+    RefSet,
+    /// `#[a, b]` is a call to `array(a, b)` and `a[i]` one to `index(a, i)`,
+    /// `push` copies the array unless nothing else uses it
+    ArrayNew,
+    ArrayIndex,
+    Len,
+    Slice,
+    Push,
+    /// `#{k: v}` is a call to `map_of(k, v)`, updates return a new map and
+    /// `get(m, k)` gives `some(v)` or `none`
+    MapNew,
+    MapInsert,
+    MapRemove,
+    MapKeys,
+    MapValues,
+    MapContains,
+    /// `a..b` and `a..=b` are lazy sequences, `take`, `drop`, `map`,
+    /// `filter` and `zip` make new ones without running them and `collect`
+    /// runs one into a list
+    Range,
+    RangeInclusive,
+    SeqTake,
+    SeqDrop,
+    SeqMap,
+    SeqFilter,
+    SeqZip,
+    SeqCollect,
+    /// `lazy e` is a call to `lazy` with a lambda computing `e`, `force`
+    /// computes it once and returns other values as they are
+    Lazy,
+    Force,
+    /// `yield e; rest` is a call to `yield` with `e` and a lambda computing
+    /// the sequence that follows, `next(s)` splits off the first element.
+    /// A yield makes a sequence rather than suspending the function it is
+    /// in, so a generator yields from its result and continues in `rest`.
+    Yield,
+    SeqNext,
+    /// `reset(lambda { e })` limits the continuations captured by
+    /// `shift(lambda k { e })` and `callcc(lambda k { e })` captures an
+    /// escape to its own result
+    Reset,
+    Shift,
+    CallCc,
+    /// `perform Ask(x)` is a call to `perform` with the effect and its
+    /// arguments, `handle e with { Ask(x) k => h }` one to `handle` with a
+    /// lambda computing `e` followed by every effect and its clause
+    Perform,
+    Handle,
+    /// `quote { e }` is a call to `quote` with a lambda computing `e`, which
+    /// makes the code of `e` instead. `eval` runs code, the others take it
+    /// apart and put it together.
+    Quote,
+    Eval,
+    CodeKind,
+    CodeParts,
+    CodeCall,
+    Lift,
+    /// `comptime { e }` is a call to `comptime` with a lambda computing `e`,
+    /// replaced by the value of `e` when the program is loaded
+    Comptime,
     // This could be extended with other primitive operations:
     // Subtract,
     // Divide,
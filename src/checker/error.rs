@@ -12,6 +12,28 @@ pub enum CheckerError {
         actual: usize,
     },
 
+    #[error("Function '{function}' has no parameter named '{name}'")]
+    UnknownArgumentName { function: String, name: String },
+
+    #[error("Argument '{name}' of function '{function}' is given more than once")]
+    DuplicateArgument { function: String, name: String },
+
+    #[error("Argument '{name}' of function '{function}' is missing and has no default")]
+    MissingArgument { function: String, name: String },
+
+    #[error("Effect '{0}' is not declared")]
+    UndefinedEffect(String),
+
+    #[error("Effect '{name}' expected {expected} arguments but got {actual}")]
+    EffectArgCountMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Constant '{0}' depends on itself")]
+    CyclicConstant(String),
+
     #[error("Cannot call primitive function '{0}' with {1} arguments")]
     InvalidPrimitiveArgCount(String, usize),
 
@@ -19,4 +41,24 @@ pub enum CheckerError {
     InternalError(String),
 }
 
+/// Problems that do not stop a program from running
+#[derive(Error, Debug)]
+pub enum CheckerWarning {
+    #[error("The last clause of function '{0}' is not a catch-all, calls no guard matches fail")]
+    MissingCatchAll(String),
+
+    #[error("Function '{function}' has the unknown attribute '{attribute}'")]
+    UnknownAttribute { function: String, attribute: String },
+
+    #[error("Function '{function}' is memoized but {reason}, which cached calls skip")]
+    ImpureMemo { function: String, reason: String },
+
+    #[error("Function '{function}' uses '{deprecated}', which is deprecated: {message}")]
+    DeprecatedUse {
+        function: String,
+        deprecated: String,
+        message: String,
+    },
+}
+
 pub type Result<T> = std::result::Result<T, CheckerError>;
@@ -1,14 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use crate::ast::attributes::{AttributeArg, BUILTIN_ATTRIBUTES};
 use crate::ast::indices::{AstIdx, NameIdx};
 use crate::ast::pool::AstPool;
 use crate::ast::primitives::PrimitiveFunc;
 use crate::ast::Ast;
-use crate::checker::error::{CheckerError, Result};
+use crate::checker::error::{CheckerError, CheckerWarning, Result};
 
 pub struct TypeChecker<'a> {
     ast_pool: &'a AstPool,
+    /// Number of parameters of every user function, not counting the rest
+    /// parameter of variadic functions
     function_param_counts: HashMap<NameIdx, usize>,
+    warnings: Vec<CheckerWarning>,
+    /// Functions and constants that use references, directly or through the
+    /// definitions they refer to
+    impure_defs: HashSet<NameIdx>,
+    /// Effects that functions and constants may perform without handling
+    /// them, directly or through the definitions they refer to
+    effect_rows: HashMap<NameIdx, HashSet<NameIdx>>,
 }
 
 impl<'a> TypeChecker<'a> {
@@ -16,27 +26,265 @@ impl<'a> TypeChecker<'a> {
         let mut function_param_counts = HashMap::new();
 
         for (&name_idx, &ast_idx) in &ast_pool.function_defs {
-            if let Ast::FunctionDef { param_count, .. } = ast_pool[ast_idx] {
-                function_param_counts.insert(name_idx, param_count);
+            if let Ast::FunctionDef {
+                param_count,
+                variadic,
+                ..
+            } = ast_pool[ast_idx]
+            {
+                function_param_counts.insert(name_idx, param_count - usize::from(variadic));
             }
         }
 
         Self {
             ast_pool,
             function_param_counts,
+            warnings: Vec::new(),
+            impure_defs: HashSet::new(),
+            effect_rows: HashMap::new(),
         }
     }
 
+    pub fn warnings(&self) -> &[CheckerWarning] {
+        &self.warnings
+    }
+
+    /// Whether a function or constant avoids mutable references. Functions
+    /// passed as arguments are not followed, so this only holds for what
+    /// the definition names itself.
+    pub fn is_pure(&self, name_idx: NameIdx) -> bool {
+        !self.impure_defs.contains(&name_idx)
+    }
+
+    /// The effects a function or constant may perform without handling
+    /// them, by name. Like [`Self::is_pure`] this only follows what the
+    /// definition names itself.
+    pub fn effect_row(&self, name_idx: NameIdx) -> Vec<&str> {
+        let mut row: Vec<&str> = self
+            .effect_rows
+            .get(&name_idx)
+            .into_iter()
+            .flatten()
+            .map(|&effect| self.ast_pool.get_string(effect))
+            .collect();
+        row.sort_unstable();
+        row
+    }
+
     pub fn check_program(&mut self) -> Result<()> {
-        /*for (&name_idx, &ast_idx) in &self.ast_pool.function_defs {
+        for (&name_idx, &ast_idx) in &self.ast_pool.function_defs {
             self.check_function_def(name_idx, ast_idx)?;
-        }*/
+            self.check_attributes(name_idx, ast_idx);
+        }
+
+        for &ast_idx in self.ast_pool.const_defs.values() {
+            self.check_expression(ast_idx)?;
+        }
+
+        if let Err(name_idx) = self.ast_pool.const_eval_order() {
+            let const_name = self.ast_pool.get_string(name_idx).to_string();
+            return Err(CheckerError::CyclicConstant(const_name));
+        }
+
+        self.mark_impure_defs();
+        self.infer_effect_rows();
+        self.check_memo_purity();
 
         Ok(())
     }
 
-    fn check_function_def(&mut self, _name_idx: NameIdx, ast_idx: AstIdx) -> Result<()> {
+    /// Warn about memoized functions whose calls do more than compute their
+    /// result, as a cached call does none of it
+    fn check_memo_purity(&mut self) {
+        let pool = self.ast_pool;
+        let mut memoized: Vec<NameIdx> = pool
+            .function_defs
+            .keys()
+            .copied()
+            .filter(|&name_idx| pool.attribute(name_idx, "memo").is_some())
+            .collect();
+        memoized.sort_by_key(|&name_idx| pool.get_string(name_idx));
+
+        for name_idx in memoized {
+            let effects = self.effect_row(name_idx);
+            let reason = if !self.is_pure(name_idx) {
+                "uses mutable references".to_string()
+            } else if !effects.is_empty() {
+                format!("performs {}", effects.join(", "))
+            } else {
+                continue;
+            };
+            self.warnings.push(CheckerWarning::ImpureMemo {
+                function: pool.get_string(name_idx).to_string(),
+                reason,
+            });
+        }
+    }
+
+    /// Grow the rows of all definitions until they include the rows of the
+    /// definitions they refer to, which may refer back to them
+    fn infer_effect_rows(&mut self) {
+        let pool = self.ast_pool;
+        let names: Vec<NameIdx> = pool
+            .function_defs
+            .keys()
+            .chain(pool.const_defs.keys())
+            .copied()
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &name_idx in &names {
+                let Some(ast_idx) = pool.get_global_def(name_idx) else {
+                    continue;
+                };
+                let row = Self::effects_of(pool, &self.effect_rows, ast_idx);
+                let known = self.effect_rows.entry(name_idx).or_default();
+                if row.len() > known.len() {
+                    *known = row;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn effects_of(
+        pool: &AstPool,
+        rows: &HashMap<NameIdx, HashSet<NameIdx>>,
+        expr_idx: AstIdx,
+    ) -> HashSet<NameIdx> {
+        let children = pool.children(expr_idx).unwrap_or_default();
+        let effects_of = |child_idx| Self::effects_of(pool, rows, child_idx);
+
+        match pool[expr_idx] {
+            Ast::UserFunc(name_idx) if pool.effects.contains_key(&name_idx) => {
+                HashSet::from([name_idx])
+            }
+            Ast::UserFunc(name_idx) => rows.get(&name_idx).cloned().unwrap_or_default(),
+            // The body performs what the clauses do not handle, which
+            // perform what they like outside of the `handle`
+            Ast::Call { func_idx, .. }
+                if matches!(pool[func_idx], Ast::PrimitiveFunc(PrimitiveFunc::Handle)) =>
+            {
+                let Some((&body_idx, clauses)) = children.split_first() else {
+                    return HashSet::new();
+                };
+                let handled: HashSet<NameIdx> = clauses
+                    .iter()
+                    .step_by(2)
+                    .filter_map(|&idx| match pool[idx] {
+                        Ast::UserFunc(name_idx) => Some(name_idx),
+                        _ => None,
+                    })
+                    .collect();
+
+                let mut row = &effects_of(body_idx) - &handled;
+                for &clause_idx in clauses.iter().skip(1).step_by(2) {
+                    row.extend(effects_of(clause_idx));
+                }
+                row
+            }
+            Ast::Call { func_idx, .. } => children
+                .into_iter()
+                .chain([func_idx])
+                .flat_map(effects_of)
+                .collect(),
+            _ => children.into_iter().flat_map(effects_of).collect(),
+        }
+    }
+
+    fn mark_impure_defs(&mut self) {
+        let pool = self.ast_pool;
+        let uses_refs = |name_idx: NameIdx| {
+            pool.get_global_def(name_idx)
+                .is_some_and(|ast_idx| Self::uses_refs(pool, ast_idx))
+        };
+
+        let names = pool.function_defs.keys().chain(pool.const_defs.keys());
+        for &name_idx in names {
+            if uses_refs(name_idx)
+                || pool
+                    .find_dependencies(pool.get_string(name_idx))
+                    .into_iter()
+                    .any(uses_refs)
+            {
+                self.impure_defs.insert(name_idx);
+            }
+        }
+    }
+
+    fn uses_refs(pool: &AstPool, expr_idx: AstIdx) -> bool {
+        match pool[expr_idx] {
+            Ast::PrimitiveFunc(
+                PrimitiveFunc::RefNew | PrimitiveFunc::RefGet | PrimitiveFunc::RefSet,
+            ) => true,
+            // Looking up a key of a map
+            Ast::Call {
+                func_idx,
+                child_count: 2,
+                ..
+            } if matches!(pool[func_idx], Ast::PrimitiveFunc(PrimitiveFunc::RefGet)) => pool
+                .children(expr_idx)
+                .unwrap_or_default()
+                .into_iter()
+                .any(|child_idx| Self::uses_refs(pool, child_idx)),
+            Ast::Call { func_idx, .. } if Self::uses_refs(pool, func_idx) => true,
+            _ => pool
+                .children(expr_idx)
+                .unwrap_or_default()
+                .into_iter()
+                .any(|child_idx| Self::uses_refs(pool, child_idx)),
+        }
+    }
+
+    /// Warn about attributes the language does not know and about uses of
+    /// deprecated functions
+    fn check_attributes(&mut self, name_idx: NameIdx, ast_idx: AstIdx) {
+        let pool = self.ast_pool;
+        let function = pool.get_string(name_idx);
+        for attribute in pool.attributes.get(&name_idx).into_iter().flatten() {
+            let attribute = pool.get_string(attribute.name);
+            if !BUILTIN_ATTRIBUTES.contains(&attribute) {
+                self.warnings.push(CheckerWarning::UnknownAttribute {
+                    function: function.to_string(),
+                    attribute: attribute.to_string(),
+                });
+            }
+        }
+
+        let start = ast_idx.0 + 1 - pool.len(ast_idx);
+        let mut used: Vec<NameIdx> = (start..=ast_idx.0)
+            .filter_map(|i| match pool[AstIdx(i)] {
+                Ast::UserFunc(used_idx) if used_idx != name_idx => Some(used_idx),
+                _ => None,
+            })
+            .collect();
+        used.sort_by_key(|&used_idx| pool.get_string(used_idx));
+        used.dedup();
+
+        for used_idx in used {
+            let Some(deprecated) = pool.attribute(used_idx, "deprecated") else {
+                continue;
+            };
+            let message = match deprecated.args.first() {
+                Some(AttributeArg::Str(message)) => message.clone(),
+                _ => "no reason given".to_string(),
+            };
+            self.warnings.push(CheckerWarning::DeprecatedUse {
+                function: function.to_string(),
+                deprecated: pool.get_string(used_idx).to_string(),
+                message,
+            });
+        }
+    }
+
+    fn check_function_def(&mut self, name_idx: NameIdx, ast_idx: AstIdx) -> Result<()> {
         if let Ast::FunctionDef { body_idx, .. } = self.ast_pool[ast_idx] {
+            let params = self.ast_pool.function_params.get(&name_idx);
+            for default in params.into_iter().flatten().filter_map(|p| p.default) {
+                self.check_expression(default)?;
+            }
             self.check_expression(body_idx)?;
 
             Ok(())
@@ -56,7 +304,10 @@ impl<'a> TypeChecker<'a> {
             Ast::UserFunc(name_idx) => {
                 let func_name = self.ast_pool.get_string(name_idx).to_string();
 
-                if !self.function_param_counts.contains_key(&name_idx) {
+                if !self.function_param_counts.contains_key(&name_idx)
+                    && !self.ast_pool.const_defs.contains_key(&name_idx)
+                    && !self.ast_pool.effects.contains_key(&name_idx)
+                {
                     return Err(CheckerError::UndefinedFunction(func_name));
                 }
 
@@ -70,45 +321,38 @@ impl<'a> TypeChecker<'a> {
                 len,
             } => {
                 self.check_expression(func_idx)?;
+                self.check_named_args(expr_idx)?;
 
+                // Calls with fewer arguments than parameters are partial
+                // applications and extra arguments are applied to the
+                // result, so only calls whose result is known not to be a
+                // function can have too many arguments.
                 match self.ast_pool[func_idx] {
-                    Ast::PrimitiveFunc(func) => match func {
-                        PrimitiveFunc::Add | PrimitiveFunc::Multiply => {
-                            if child_count != 2 {
-                                let func_name = match func {
-                                    PrimitiveFunc::Add => "add",
-                                    PrimitiveFunc::Multiply => "multiply",
-                                };
-
-                                return Err(CheckerError::InvalidPrimitiveArgCount(
-                                    func_name.to_string(),
-                                    child_count,
-                                ));
-                            }
+                    Ast::PrimitiveFunc(func) => {
+                        if matches!(func, PrimitiveFunc::Perform | PrimitiveFunc::Handle) {
+                            self.check_effect_call(expr_idx, func)?;
                         }
-                    },
-
-                    Ast::UserFunc(name_idx) => {
-                        let func_name = self.ast_pool.get_string(name_idx).to_string();
-
-                        if let Some(&expected_count) = self.function_param_counts.get(&name_idx) {
-                            if expected_count != child_count {
-                                return Err(CheckerError::ArgumentCountMismatch {
-                                    name: func_name,
-                                    expected: expected_count,
-                                    actual: child_count,
-                                });
-                            }
-                        } else {
-                            return Err(CheckerError::UndefinedFunction(func_name));
+
+                        let max_args = Self::primitive_max_args(func);
+                        if max_args.is_some_and(|max_args| child_count > max_args) {
+                            let func_name = self.ast_pool.get_primitive_name(func);
+
+                            return Err(CheckerError::InvalidPrimitiveArgCount(
+                                func_name.to_string(),
+                                child_count,
+                            ));
                         }
                     }
 
-                    _ => {
+                    Ast::UserFunc(name_idx) => self.check_user_call(name_idx, child_count)?,
+
+                    Ast::Integer(_) => {
                         return Err(CheckerError::InternalError(
                             "Cannot call non-function expression".to_string(),
                         ));
                     }
+
+                    _ => {}
                 }
 
                 // Check all the children (arguments)
@@ -121,11 +365,304 @@ impl<'a> TypeChecker<'a> {
                 Ok(())
             }
 
-            Ast::FunctionDef { body_idx, .. } => self.check_expression(body_idx),
-            Ast::Lambda {
-                param_count,
+            Ast::FunctionDef { body_idx, .. } | Ast::ConstDef { body_idx, .. } => {
+                self.check_expression(body_idx)
+            }
+            Ast::Lambda { body_idx, .. } | Ast::Try { body_idx } => self.check_expression(body_idx),
+            Ast::TryCatch {
                 body_idx,
-            } => Ok(()),
+                handler_idx,
+            } => {
+                self.check_expression(body_idx)?;
+                self.check_expression(handler_idx)
+            }
+            Ast::MacroCall { expansion_idx, .. } => self.check_expression(expansion_idx),
+            // The block was checked before it was evaluated
+            Ast::Comptime { value_idx, .. } => self.check_expression(value_idx),
+            Ast::Guards {
+                name_idx,
+                child_count,
+                ..
+            } => {
+                // A catch-all clause leaves a value without a condition
+                if child_count % 2 == 0 {
+                    let func_name = self.ast_pool.get_string(name_idx).to_string();
+                    self.warnings
+                        .push(CheckerWarning::MissingCatchAll(func_name));
+                }
+
+                for child_idx in self.ast_pool.children(expr_idx).unwrap_or_default() {
+                    self.check_expression(child_idx)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The most arguments a primitive can be called with, if its result is
+    /// known not to be a function
+    fn primitive_max_args(func: PrimitiveFunc) -> Option<usize> {
+        match func {
+            PrimitiveFunc::Add
+            | PrimitiveFunc::Multiply
+            | PrimitiveFunc::Equal
+            | PrimitiveFunc::Less
+            | PrimitiveFunc::Greater
+            | PrimitiveFunc::RefSet
+            | PrimitiveFunc::ArrayIndex
+            | PrimitiveFunc::Push
+            | PrimitiveFunc::MapRemove
+            | PrimitiveFunc::MapContains
+            | PrimitiveFunc::Range
+            | PrimitiveFunc::RangeInclusive
+            | PrimitiveFunc::SeqTake
+            | PrimitiveFunc::SeqDrop
+            | PrimitiveFunc::SeqMap
+            | PrimitiveFunc::SeqFilter
+            | PrimitiveFunc::SeqZip
+            | PrimitiveFunc::Yield
+            | PrimitiveFunc::CodeCall => Some(2),
+            PrimitiveFunc::Slice | PrimitiveFunc::MapInsert => Some(3),
+            PrimitiveFunc::OptionSome
+            | PrimitiveFunc::ResultOk
+            | PrimitiveFunc::ResultErr
+            | PrimitiveFunc::Raise
+            | PrimitiveFunc::ErrorMessage
+            | PrimitiveFunc::ErrorPayload
+            | PrimitiveFunc::RefNew
+            | PrimitiveFunc::Len
+            | PrimitiveFunc::MapKeys
+            | PrimitiveFunc::MapValues
+            | PrimitiveFunc::SeqCollect
+            | PrimitiveFunc::Lazy
+            | PrimitiveFunc::SeqNext
+            | PrimitiveFunc::Quote
+            | PrimitiveFunc::Comptime
+            | PrimitiveFunc::CodeKind
+            | PrimitiveFunc::CodeParts
+            | PrimitiveFunc::Lift => Some(1),
+            // Take any number of items
+            PrimitiveFunc::ArrayNew | PrimitiveFunc::MapNew => None,
+            // `none` is a value
+            PrimitiveFunc::OptionNone => Some(0),
+            // Their results are functions or may be ones
+            PrimitiveFunc::Pipe
+            | PrimitiveFunc::Compose
+            | PrimitiveFunc::Fold
+            | PrimitiveFunc::Force
+            | PrimitiveFunc::RefGet
+            | PrimitiveFunc::Reset
+            | PrimitiveFunc::Shift
+            | PrimitiveFunc::CallCc
+            | PrimitiveFunc::Eval => None,
+            // Checked by `check_effect_call`
+            PrimitiveFunc::Perform | PrimitiveFunc::Handle => None,
+        }
+    }
+
+    /// A call of a user function can only have more arguments than the
+    /// function has parameters when its result may be a function
+    fn check_user_call(&self, name_idx: NameIdx, child_count: usize) -> Result<()> {
+        let pool = self.ast_pool;
+        let Some(&expected) = self.function_param_counts.get(&name_idx) else {
+            return Ok(());
+        };
+        let Some(Ast::FunctionDef {
+            variadic: false,
+            body_idx,
+            ..
+        }) = pool
+            .function_defs
+            .get(&name_idx)
+            .map(|&ast_idx| &pool[ast_idx])
+        else {
+            return Ok(());
+        };
+        let has_defaults = pool
+            .function_params
+            .get(&name_idx)
+            .is_some_and(|params| params.iter().any(|param| param.default.is_some()));
+
+        if child_count > expected
+            && !has_defaults
+            && self.returns_non_function(*body_idx, &mut HashSet::from([name_idx]))
+        {
+            return Err(CheckerError::ArgumentCountMismatch {
+                name: pool.get_string(name_idx).to_string(),
+                expected,
+                actual: child_count,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the value of an expression is known not to be a function.
+    /// Recursive calls of the functions in `visiting` give the value of
+    /// one of their other branches, so they are taken to be none.
+    fn returns_non_function(&self, expr_idx: AstIdx, visiting: &mut HashSet<NameIdx>) -> bool {
+        let pool = self.ast_pool;
+        match pool[expr_idx] {
+            Ast::Integer(_) => true,
+            Ast::Call {
+                func_idx,
+                child_count,
+                ..
+            } => match pool[func_idx] {
+                Ast::PrimitiveFunc(PrimitiveFunc::ArrayNew | PrimitiveFunc::MapNew) => true,
+                // They give back values stored earlier, which may be functions
+                Ast::PrimitiveFunc(
+                    PrimitiveFunc::RefGet | PrimitiveFunc::ArrayIndex | PrimitiveFunc::ErrorPayload,
+                ) => false,
+                Ast::PrimitiveFunc(func) => Self::primitive_max_args(func) == Some(child_count),
+                // The bindings of a `where` clause
+                Ast::Lambda {
+                    param_count,
+                    body_idx,
+                } => param_count == child_count && self.returns_non_function(body_idx, visiting),
+                Ast::UserFunc(name_idx) if visiting.contains(&name_idx) => true,
+                Ast::UserFunc(name_idx) => {
+                    let Some(&Ast::FunctionDef {
+                        param_count,
+                        variadic: false,
+                        body_idx,
+                        ..
+                    }) = pool
+                        .function_defs
+                        .get(&name_idx)
+                        .map(|&ast_idx| &pool[ast_idx])
+                    else {
+                        return false;
+                    };
+                    visiting.insert(name_idx);
+                    let result =
+                        param_count == child_count && self.returns_non_function(body_idx, visiting);
+                    visiting.remove(&name_idx);
+                    result
+                }
+                _ => false,
+            },
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => {
+                self.returns_non_function(body_idx, visiting)
+                    && self.returns_non_function(handler_idx, visiting)
+            }
+            Ast::MacroCall { expansion_idx, .. } => {
+                self.returns_non_function(expansion_idx, visiting)
+            }
+            // The values of the clauses, after their conditions
+            Ast::Guards { .. } => {
+                let children = pool.children(expr_idx).unwrap_or_default();
+                children
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| i % 2 == 1 || i + 1 == children.len())
+                    .all(|(_, &child_idx)| self.returns_non_function(child_idx, visiting))
+            }
+            _ => false,
+        }
+    }
+
+    /// `perform` and `handle` name declared effects and give them as many
+    /// arguments as they were declared with
+    fn check_effect_call(&self, call_idx: AstIdx, func: PrimitiveFunc) -> Result<()> {
+        let pool = self.ast_pool;
+        let children = pool.children(call_idx).unwrap_or_default();
+        let check_effect = |effect_idx: AstIdx, actual: usize| {
+            let Ast::UserFunc(name_idx) = pool[effect_idx] else {
+                return Err(CheckerError::InternalError(format!(
+                    "Expected an effect but got {:?}",
+                    pool[effect_idx]
+                )));
+            };
+            let name = pool.get_string(name_idx).to_string();
+            let decl = pool
+                .effects
+                .get(&name_idx)
+                .ok_or_else(|| CheckerError::UndefinedEffect(name.clone()))?;
+
+            if decl.params.len() != actual {
+                return Err(CheckerError::EffectArgCountMismatch {
+                    name,
+                    expected: decl.params.len(),
+                    actual,
+                });
+            }
+            Ok(())
+        };
+
+        match func {
+            PrimitiveFunc::Perform => match children.split_first() {
+                Some((&effect_idx, args)) => check_effect(effect_idx, args.len()),
+                None => Ok(()),
+            },
+            // Handlers take the arguments of the effect and the continuation
+            _ => {
+                for clause in children.get(1..).unwrap_or_default().chunks(2) {
+                    if let [effect_idx, handler_idx] = *clause
+                        && let Ast::Lambda { param_count, .. } = pool[handler_idx]
+                    {
+                        check_effect(effect_idx, param_count.saturating_sub(1))?;
+                    }
+                }
+                Ok(())
+            }
         }
     }
+
+    /// Named arguments must be parameters of the callee, given at most once
+    /// counting the positional arguments, and the parameters left out must
+    /// have a default
+    fn check_named_args(&self, call_idx: AstIdx) -> Result<()> {
+        let Some(named_args) = self.ast_pool.named_args.get(&call_idx) else {
+            return Ok(());
+        };
+
+        let pool = self.ast_pool;
+        let params = pool
+            .function_params
+            .get(&named_args.callee)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut given = vec![false; params.len()];
+        for flag in given.iter_mut().take(named_args.positional) {
+            *flag = true;
+        }
+
+        for &name in &named_args.names {
+            let function = pool.get_string(named_args.callee).to_string();
+            let name_str = pool.get_string(name).to_string();
+
+            match params.iter().position(|param| param.name == name) {
+                None => {
+                    return Err(CheckerError::UnknownArgumentName {
+                        function,
+                        name: name_str,
+                    });
+                }
+                Some(i) if given[i] => {
+                    return Err(CheckerError::DuplicateArgument {
+                        function,
+                        name: name_str,
+                    });
+                }
+                Some(i) => given[i] = true,
+            }
+        }
+
+        // A call with named arguments is never a partial application
+        let missing = params
+            .iter()
+            .zip(given)
+            .find(|(param, given)| !given && param.default.is_none());
+        if let Some((param, _)) = missing {
+            return Err(CheckerError::MissingArgument {
+                function: pool.get_string(named_args.callee).to_string(),
+                name: pool.get_string(param.name).to_string(),
+            });
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,308 @@
+//! First-class continuations for a runtime that keeps its control state on
+//! the Rust stack, which cannot be copied.
+//!
+//! `callcc` continuations escape: calling one unwinds to its `callcc`, which
+//! must still be running. `reset`/`shift` continuations can be called any
+//! number of times. A `reset` body is run again to resume it, with every
+//! `shift` it already passed returning the value it was resumed with then.
+//!
+//! Effect handlers resume the same way: a `handle` body is run again with
+//! every `perform` it already passed returning the value it was resumed with.
+//!
+//! Replaying is only faithful for code without side effects, and makes a
+//! body resumed after its n-th `shift` or `perform` do the work before it n
+//! times over. The only side effects of the language are references, which
+//! fail while a body is replayed, see [`check_not_replaying`]. They can be
+//! used freely after the last `shift` or `perform` that was passed.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::compiler::function::{CompiledFunction, ErrTrace};
+use crate::value::Value;
+
+/// A running `reset` body
+struct ResetFrame {
+    id: u64,
+    body: CompiledFunction,
+    /// Values of the `shift`s passed so far in this run
+    past: Vec<Value>,
+    /// Values for the next `shift`s when resuming, the next one last
+    future: Vec<Value>,
+}
+
+/// The handlers of a `handle`, by effect
+type Clauses = Rc<[(Rc<str>, CompiledFunction)]>;
+
+/// A running `handle` body
+struct HandlerFrame {
+    id: u64,
+    body: CompiledFunction,
+    clauses: Clauses,
+    /// Values of the `perform`s handled here passed so far in this run
+    past: Vec<Value>,
+    /// Values for the next `perform`s when resuming, the next one last
+    future: Vec<Value>,
+}
+
+#[derive(Default)]
+struct Continuations {
+    next_id: u64,
+    resets: Vec<ResetFrame>,
+    handlers: Vec<HandlerFrame>,
+}
+
+thread_local! {
+    static CONTINUATIONS: RefCell<Continuations> = RefCell::default();
+}
+
+fn fresh_id() -> u64 {
+    CONTINUATIONS.with_borrow_mut(|state| {
+        state.next_id += 1;
+        state.next_id
+    })
+}
+
+/// Fail if a `reset` or `handle` body is running again to be resumed and has
+/// not yet reached the `shift` or `perform` it is resumed from, where `what`
+/// would be done again or see what the resumptions did since
+pub fn check_not_replaying(what: &str) -> Result<(), ErrTrace> {
+    let replaying = CONTINUATIONS.with_borrow(|state| {
+        state.resets.iter().any(|frame| !frame.future.is_empty())
+            || state.handlers.iter().any(|frame| !frame.future.is_empty())
+    });
+    if replaying {
+        return Err(ErrTrace::new(format!(
+            "cannot {} before the shift or perform a continuation resumes from",
+            what
+        )));
+    }
+    Ok(())
+}
+
+/// `callcc(f)` calls `f` with a function returning its argument from the
+/// `callcc` call
+pub fn callcc(f: CompiledFunction, mem: &mut Vec<Value>) -> Result<(), ErrTrace> {
+    let id = fresh_id();
+    let running = Rc::new(Cell::new(true));
+    let k_running = running.clone();
+    let k = CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            if !k_running.get() {
+                return Err(ErrTrace::new(
+                    "continuation called after its callcc returned",
+                ));
+            }
+            let value = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
+            Err(ErrTrace::escape(id, value))
+        },
+        1,
+    );
+
+    let base = mem.len();
+    mem.push(Value::Fun(k));
+    let result = f.apply(mem, 1);
+    running.set(false);
+    match result {
+        Err(error) => {
+            let value = error.escaped_to(id)?;
+            mem.truncate(base);
+            mem.push(value);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// `reset(body)` calls `body`, which takes no arguments, as the limit of
+/// the continuations captured by `shift` within it
+pub fn reset(body: CompiledFunction, mem: &mut Vec<Value>) -> Result<(), ErrTrace> {
+    run_reset(body, Vec::new(), mem)
+}
+
+/// Run a `reset` body, resuming it with `resumed` as the values of its
+/// first `shift`s
+fn run_reset(
+    body: CompiledFunction,
+    mut resumed: Vec<Value>,
+    mem: &mut Vec<Value>,
+) -> Result<(), ErrTrace> {
+    let id = fresh_id();
+    resumed.reverse();
+    CONTINUATIONS.with_borrow_mut(|state| {
+        state.resets.push(ResetFrame {
+            id,
+            body: body.clone(),
+            past: Vec::new(),
+            future: resumed,
+        })
+    });
+
+    let base = mem.len();
+    let result = body.apply(mem, 0);
+    CONTINUATIONS.with_borrow_mut(|state| state.resets.pop());
+
+    match result {
+        // A `shift` left the body, what it computes is the result
+        Err(error) => match error.escaped_to(id)? {
+            Value::Fun(shifted) => {
+                mem.truncate(base);
+                run_reset(shifted, Vec::new(), mem)
+            }
+            other => Err(ErrTrace::new(format!(
+                "reset expected a shift to leave a function but got {:?}",
+                other
+            ))),
+        },
+        result => result,
+    }
+}
+
+/// `shift(f)` calls `f` with the rest of the enclosing `reset` body as a
+/// function and makes its result the result of the `reset`
+pub fn shift(f: CompiledFunction, mem: &mut Vec<Value>) -> Result<(), ErrTrace> {
+    // Resuming, this `shift` was already passed and returns the same value
+    let resumed = CONTINUATIONS.with_borrow_mut(|state| {
+        let frame = state.resets.last_mut()?;
+        let value = frame.future.pop();
+        frame.past.extend(value.clone());
+        Some((value, frame.id, frame.body.clone(), frame.past.clone()))
+    });
+    let (id, body, past) = match resumed {
+        None => return Err(ErrTrace::new("shift called outside of reset")),
+        Some((Some(value), ..)) => {
+            mem.push(value);
+            return Ok(());
+        }
+        Some((None, id, body, past)) => (id, body, past),
+    };
+
+    let k = CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            let value = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
+            let mut resumed = past.clone();
+            resumed.push(value);
+            run_reset(body.clone(), resumed, mem)
+        },
+        1,
+    );
+
+    // `f(k)` runs in place of the `reset` body, under a `reset` of its own
+    let shifted = CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            mem.push(Value::Fun(k.clone()));
+            f.apply(mem, 1)
+        },
+        0,
+    );
+    Err(ErrTrace::escape(id, Value::Fun(shifted)))
+}
+
+/// `handle(body, clauses)` calls `body`, which takes no arguments, handling
+/// the effects it performs with the handler given for them in `clauses`
+pub fn handle(
+    body: CompiledFunction,
+    clauses: Clauses,
+    mem: &mut Vec<Value>,
+) -> Result<(), ErrTrace> {
+    run_handler(body, clauses, Vec::new(), mem)
+}
+
+/// Run a `handle` body, resuming it with `resumed` as the values of its
+/// first handled `perform`s
+fn run_handler(
+    body: CompiledFunction,
+    clauses: Clauses,
+    mut resumed: Vec<Value>,
+    mem: &mut Vec<Value>,
+) -> Result<(), ErrTrace> {
+    let id = fresh_id();
+    resumed.reverse();
+    CONTINUATIONS.with_borrow_mut(|state| {
+        state.handlers.push(HandlerFrame {
+            id,
+            body: body.clone(),
+            clauses,
+            past: Vec::new(),
+            future: resumed,
+        })
+    });
+
+    let base = mem.len();
+    let result = body.apply(mem, 0);
+    CONTINUATIONS.with_borrow_mut(|state| state.handlers.pop());
+
+    match result {
+        // A `perform` left the body, its handler computes the result. The
+        // handler runs outside of the `handle`, which its continuation
+        // installs again.
+        Err(error) => match error.escaped_to(id)? {
+            Value::Fun(handler) => {
+                mem.truncate(base);
+                handler.apply(mem, 0)
+            }
+            other => Err(ErrTrace::new(format!(
+                "handle expected a perform to leave a function but got {:?}",
+                other
+            ))),
+        },
+        result => result,
+    }
+}
+
+/// `perform(effect, args)` calls the handler of the closest `handle` for
+/// `effect` with `args` and the rest of that `handle` body as a function
+pub fn perform(effect: Rc<str>, args: Rc<[Value]>, mem: &mut Vec<Value>) -> Result<(), ErrTrace> {
+    // Resuming, this `perform` was already passed and returns the same value
+    let resumed = CONTINUATIONS.with_borrow_mut(|state| {
+        let frame = state
+            .handlers
+            .iter_mut()
+            .rev()
+            .find(|frame| frame.clauses.iter().any(|(name, _)| *name == effect))?;
+        let value = frame.future.pop();
+        frame.past.extend(value.clone());
+        Some((
+            value,
+            frame.id,
+            frame.body.clone(),
+            frame.clauses.clone(),
+            frame.past.clone(),
+        ))
+    });
+    let (id, body, clauses, past) = match resumed {
+        None => return Err(ErrTrace::new(format!("unhandled effect `{effect}`"))),
+        Some((Some(value), ..)) => {
+            mem.push(value);
+            return Ok(());
+        }
+        Some((None, id, body, clauses, past)) => (id, body, clauses, past),
+    };
+
+    let handler = clauses
+        .iter()
+        .find(|(name, _)| *name == effect)
+        .map(|(_, handler)| handler.clone())
+        .ok_or(ErrTrace::new(format!("unhandled effect `{effect}`")))?;
+
+    let k = CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            let value = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
+            let mut resumed = past.clone();
+            resumed.push(value);
+            run_handler(body.clone(), clauses.clone(), resumed, mem)
+        },
+        1,
+    );
+
+    // The handler takes the arguments of the effect, then the continuation
+    let handled = CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            mem.push(Value::Fun(k.clone()));
+            mem.extend(args.iter().rev().cloned());
+            handler.apply(mem, args.len() + 1)
+        },
+        0,
+    );
+    Err(ErrTrace::escape(id, Value::Fun(handled)))
+}
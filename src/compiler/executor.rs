@@ -1,24 +1,42 @@
-use std::collections::{HashMap, HashSet};
-use std::ops::Sub;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 
-use crate::ast::indices::{AstIdx, FunIdx, NameIdx};
+use crate::ast::indices::{AstIdx, FunIdx, NameIdx, ParamIdx};
+use crate::ast::params::NamedArgs;
 use crate::ast::pool::AstPool;
 use crate::ast::Ast;
-use crate::compiler::function::CompiledFunction;
-use crate::value::Value;
+use crate::compiler::continuation;
+use crate::compiler::function::{self, CompiledFunction};
+use crate::compiler::memo::MemoCache;
+use crate::value::code::Globals;
+use crate::value::{Array, Code, Map, Seq, Thunk, Value};
 
 use super::function::ErrTrace;
+
+/// Calls a `comptime` block may make before its evaluation is stopped
+const COMPTIME_STEP_LIMIT: u64 = 1_000_000;
+/// Nested calls of compiled functions a `comptime` block may make, low
+/// enough for the 2 MiB stack of a spawned thread in a debug build. Every
+/// call of a user function nests a few of them.
+const COMPTIME_DEPTH_LIMIT: usize = 400;
+
 pub struct CompiledFunctions {
     functions: Vec<CompiledFunction>,
     function_defs: HashMap<NameIdx, FunIdx>,
+    /// Values of the top-level constants, filled in once they are evaluated
+    constants: HashMap<NameIdx, Rc<RefCell<Option<Value>>>>,
+    /// Results of the functions marked `#[memo]`
+    memo_caches: HashMap<NameIdx, MemoCache>,
 }
 
 #[derive(Debug, Clone)]
 pub struct CompilationContext {
     stack_size: usize,
+    /// Stack size at the entry of every enclosing function or lambda body
     frames: Vec<usize>,
-    arg_count: Vec<usize>,
+    /// Variables captured by every enclosing body, as (level, offset)
+    captures: Vec<Rc<[(usize, usize)]>>,
 }
 
 impl CompilationContext {
@@ -26,7 +44,7 @@ impl CompilationContext {
         Self {
             stack_size: 0,
             frames: vec![0],
-            arg_count: vec![0],
+            captures: vec![Rc::from([])],
         }
     }
 
@@ -35,32 +53,36 @@ impl CompilationContext {
     }
 
     pub fn dealloc(&mut self, n: usize) {
-        self.stack_size.sub(n);
+        self.stack_size -= n;
     }
 
     fn stack_depth(&self) -> usize {
         self.stack_size
     }
 
-    fn calculate_param_offset(&self, level: usize, offset: usize) -> usize {
-        let ret = if level == self.frames.len() {
-            self.stack_size - (self.frames[level - 1] + offset)
+    /// Distance from the top of the stack to the given parameter. Parameters
+    /// of the innermost body sit below its captures, captured variables are
+    /// pushed in order on entry so the last one is closest to the top.
+    fn calculate_param_offset(&self, level: usize, offset: usize) -> Option<usize> {
+        let depth = self.stack_size - self.frames.last()?;
+        let captures = self.captures.last()?;
+
+        if level == self.frames.len() {
+            Some(depth + captures.len() + offset)
         } else {
-            self.stack_size
-                - (self.frames.last().unwrap() + self.arg_count.last().unwrap() + offset)
-        };
-        ret
+            let pos = captures.iter().position(|&c| c == (level, offset))?;
+            Some(depth + captures.len() - 1 - pos)
+        }
     }
 
-    fn enter_scope(&mut self, param_count: usize) {
-        let s = self.stack_size;
-        self.frames.push(s);
-        self.arg_count.push(param_count);
-        self.stack_size += param_count;
+    fn enter_scope(&mut self, captures: Rc<[(usize, usize)]>) {
+        self.frames.push(self.stack_size);
+        self.captures.push(captures);
     }
 
     fn exit_scope(&mut self) {
         let last = self.frames.pop().unwrap();
+        self.captures.pop();
         self.stack_size = last;
     }
 }
@@ -69,9 +91,22 @@ impl CompiledFunctions {
         Self {
             functions: Vec::new(),
             function_defs: HashMap::new(),
+            constants: HashMap::new(),
+            memo_caches: HashMap::new(),
         }
     }
 
+    /// The caches of the `#[memo]` functions, by name
+    pub fn memo_caches(&self) -> &HashMap<NameIdx, MemoCache> {
+        &self.memo_caches
+    }
+
+    /// Reuse the caches of the same program in the next `compile`, to keep
+    /// the results of a program that is compiled again without changing them
+    pub fn keep_memo_caches(&mut self, caches: HashMap<NameIdx, MemoCache>) {
+        self.memo_caches = caches;
+    }
+
     fn find_captured_vars(
         &self,
         node: AstIdx,
@@ -109,7 +144,7 @@ impl CompiledFunctions {
             }
 
             Ast::Lambda { body_idx, .. } => {
-                self.collect_captured_vars(*body_idx, pool, lambda_level + 1, captured);
+                self.collect_captured_vars(*body_idx, pool, lambda_level, captured);
             }
 
             _ => {
@@ -118,12 +153,16 @@ impl CompiledFunctions {
                         self.collect_captured_vars(child, pool, lambda_level, captured);
                     }
                 }
+                // The callee is not among the children but may use variables too
+                if let Ast::Call { func_idx, .. } = pool[node] {
+                    self.collect_captured_vars(func_idx, pool, lambda_level, captured);
+                }
             }
         }
     }
 
     pub fn debug_captured_vars(&self, node: AstIdx, pool: &AstPool) -> String {
-        let captures = self.find_captured_vars(node, pool, 1);
+        let captures = self.find_captured_vars(node, pool, 2);
         let mut result = String::new();
 
         result.push_str(&format!("Found {} captured variables:\n", captures.len()));
@@ -162,12 +201,33 @@ impl CompiledFunctions {
             Ast::Integer(i) => compile_integer(context, i),
             Ast::ParamRef { offset, level, .. } => compile_param(context, offset, level),
             Ast::PrimitiveFunc(primitive_func) => compile_primitive_func(context, primitive_func),
+            Ast::UserFunc(name_idx) if pool.effects.contains_key(&name_idx) => {
+                compile_effect(context, pool.get_string(name_idx))
+            }
             Ast::UserFunc(name_idx) => self.compile_user_func(context, name_idx),
             Ast::Lambda {
                 param_count,
                 body_idx,
             } => self.compile_lambda(node, pool, context, param_count, body_idx),
 
+            Ast::Call {
+                func_idx,
+                child_count: 1,
+                child_start,
+                ..
+            } if matches!(
+                pool[func_idx],
+                Ast::PrimitiveFunc(crate::ast::PrimitiveFunc::Quote)
+            ) =>
+            {
+                match pool[child_start] {
+                    Ast::Lambda {
+                        param_count: 0,
+                        body_idx,
+                    } => self.compile_quote(pool, context, body_idx),
+                    _ => self.compile_call(node, pool, context, func_idx),
+                }
+            }
             Ast::Call {
                 func_idx,
                 child_count,
@@ -177,11 +237,129 @@ impl CompiledFunctions {
             Ast::FunctionDef {
                 body_idx,
                 param_count,
+                variadic,
                 ..
-            } => self.compile_fun_def(pool, context, body_idx, param_count),
+            } => self.compile_fun_def(pool, context, body_idx, param_count, variadic),
+            Ast::ConstDef { body_idx, .. } => {
+                self.compile_fun_def(pool, context, body_idx, 0, false)
+            }
+            Ast::Guards { name_idx, .. } => self.compile_guards(node, pool, context, name_idx),
+            Ast::Try { body_idx } => self.compile_try(pool, context, body_idx),
+            Ast::TryCatch {
+                body_idx,
+                handler_idx,
+            } => self.compile_try_catch(pool, context, body_idx, handler_idx),
+            Ast::MacroCall { expansion_idx, .. } => self.compile_expr(expansion_idx, pool, context),
+            Ast::Comptime { value_idx, .. } => self.compile_expr(value_idx, pool, context),
         }
     }
 
+    fn compile_try_catch(
+        &self,
+        pool: &AstPool,
+        context: &mut CompilationContext,
+        body_idx: AstIdx,
+        handler_idx: AstIdx,
+    ) -> Option<CompiledFunction> {
+        // The handler runs on the stack as it was before the body
+        let body = self.compile_expr(body_idx, pool, context)?;
+        context.dealloc(1);
+        let handler = self.compile_expr(handler_idx, pool, context)?;
+
+        Some(CompiledFunction::new(
+            move |mem: &mut Vec<Value>| {
+                let base = mem.len();
+                match body.call(mem) {
+                    Err(error) if !error.is_control_flow() => {
+                        mem.truncate(base);
+                        handler.call(mem)?;
+                        let Value::Fun(handler) = pop_arg(mem)? else {
+                            return Err(ErrTrace::new("catch handler is not a function"));
+                        };
+                        mem.push(Value::Error(Rc::new(error)));
+                        handler.apply(mem, 1)
+                    }
+                    result => result,
+                }
+            },
+            0,
+        ))
+    }
+
+    fn compile_try(
+        &self,
+        pool: &AstPool,
+        context: &mut CompilationContext,
+        body_idx: AstIdx,
+    ) -> Option<CompiledFunction> {
+        let body = self.compile_expr(body_idx, pool, context)?;
+
+        Some(CompiledFunction::new(
+            move |mem: &mut Vec<Value>| {
+                body.call(mem)?;
+                match pop_arg(mem)? {
+                    Value::Option(Some(value)) | Value::Result(Ok(value)) => {
+                        mem.push(Rc::unwrap_or_clone(value));
+                        Ok(())
+                    }
+                    failure @ (Value::Option(None) | Value::Result(Err(_))) => {
+                        Err(ErrTrace::early_return(failure))
+                    }
+                    other => Err(ErrTrace::new(format!(
+                        "`?` expects an Option or a Result but got {:?}",
+                        other
+                    ))),
+                }
+            },
+            0,
+        ))
+    }
+
+    fn compile_guards(
+        &self,
+        node: AstIdx,
+        pool: &AstPool,
+        context: &mut CompilationContext,
+        name_idx: NameIdx,
+    ) -> Option<CompiledFunction> {
+        // Every condition and value is evaluated on the same stack, the
+        // condition is popped before the value is pushed
+        let mut children = Vec::new();
+        for child_idx in pool.children(node).unwrap_or_default() {
+            children.push(self.compile_expr(child_idx, pool, context)?);
+            context.dealloc(1);
+        }
+        context.alloc(1);
+
+        let func_name = pool.get_string(name_idx).to_string();
+        Some(CompiledFunction::new(
+            move |mem: &mut Vec<Value>| {
+                for clause in children.chunks(2) {
+                    let [condition, value] = clause else {
+                        return clause[0].call(mem);
+                    };
+
+                    condition.call(mem)?;
+                    match pop_arg(mem)? {
+                        Value::Bool(true) => return value.call(mem),
+                        Value::Bool(false) => {}
+                        other => {
+                            return Err(ErrTrace::new(format!(
+                                "guard of '{}' evaluated to {:?} instead of a Bool",
+                                func_name, other
+                            )));
+                        }
+                    }
+                }
+                Err(ErrTrace::new(format!(
+                    "no clause of '{}' matched its arguments",
+                    func_name
+                )))
+            },
+            0,
+        ))
+    }
+
     fn compile_call<'a>(
         &'a self,
         node: AstIdx,
@@ -189,50 +367,130 @@ impl CompiledFunctions {
         context: &mut CompilationContext,
         func_idx: AstIdx,
     ) -> Option<CompiledFunction> {
-        let children = pool.children(node)?;
-        let mut child_lambdas = Vec::with_capacity(children.len());
-        // context.alloc(1);
-        let func = self.compile_expr(func_idx, pool, context)?;
+        let children = pool.children(node).unwrap_or_default();
+        let argc = children.len();
 
+        // Arguments are evaluated in order and then reversed so the first
+        // one ends up on top
+        let mut child_lambdas = Vec::with_capacity(argc);
         for &child_idx in children.iter() {
-            //context.alloc(1);
-            let child_lambda = self.compile_expr(child_idx, pool, context)?;
-            child_lambdas.push(child_lambda);
+            child_lambdas.push(self.compile_expr(child_idx, pool, context)?);
+        }
+        let func = self.compile_expr(func_idx, pool, context)?;
+        context.dealloc(argc);
+
+        if let Some(named_args) = pool.named_args.get(&node) {
+            let order = named_arg_order(named_args, pool)?;
+            return Some(CompiledFunction::new(
+                move |mem: &mut Vec<Value>| {
+                    for lambda in child_lambdas.iter() {
+                        lambda.call(mem)?;
+                    }
+                    let written: Vec<Value> = mem.drain(mem.len() - argc..).collect();
+                    func.call(mem)?;
+                    let Value::Fun(fun) = mem.pop().ok_or(ErrTrace::new("stack underflow"))? else {
+                        return Err(ErrTrace::new("named arguments passed to a non-function"));
+                    };
+
+                    // The first parameter ends up on top
+                    for (index, arg) in order.iter().enumerate().rev() {
+                        match (arg, fun.default_for(index)) {
+                            (Some(arg), _) => mem.push(written[*arg].clone()),
+                            (None, Some(default)) => default.call(mem)?,
+                            (None, None) => {
+                                return Err(ErrTrace::new(format!(
+                                    "missing argument {} without a default",
+                                    index + 1
+                                )));
+                            }
+                        }
+                    }
+                    fun.apply(mem, order.len())
+                },
+                0,
+            ));
         }
-        context.dealloc(children.len() + 1);
 
         Some(CompiledFunction::new(
             move |mem: &mut Vec<Value>| {
-                let start_len = mem.len();
-                for (i, lambda) in child_lambdas.iter().rev().enumerate() {
+                for lambda in child_lambdas.iter() {
                     lambda.call(mem)?;
                 }
+                let args_start = mem.len() - argc;
+                mem[args_start..].reverse();
                 func.call(mem)?;
                 let func_val = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
 
                 match func_val {
-                    Value::Fun(fun) => {
-                        let expected_args = fun.param_count;
-                        if mem.len() < expected_args {
-                            return Err(ErrTrace::new(format!(
-                                "not enough arguments for function call: expected {}, got {}",
-                                expected_args,
-                                mem.len()
-                            )));
-                        }
+                    Value::Fun(fun) => fun.apply(mem, argc),
+                    _ => Err(ErrTrace::new(
+                        "expected function or lambda but got something else",
+                    )),
+                }
+            },
+            0,
+        ))
+    }
 
-                        fun.call(mem)?;
-                    }
-                    _ => {
-                        return Err(ErrTrace::new(
-                            "expected function or lambda but got something else",
-                        ));
-                    }
-                };
+    /// `quote { e }` makes the code of `e`, copied into a pool of its own
+    /// where the parameters of the enclosing bodies it uses become parameters
+    /// of level 1, whose values are taken from the stack when it runs
+    fn compile_quote(
+        &self,
+        pool: &AstPool,
+        context: &mut CompilationContext,
+        body_idx: AstIdx,
+    ) -> Option<CompiledFunction> {
+        let level = context.frames.len();
+        let start = body_idx.0 + 1 - pool.len(body_idx);
+        let captured: BTreeMap<(usize, usize), NameIdx> = (start..=body_idx.0)
+            .filter_map(|i| match pool[AstIdx(i)] {
+                Ast::ParamRef {
+                    name,
+                    level: l,
+                    offset,
+                } if l > 0 && l <= level => Some(((l, offset.0), name)),
+                _ => None,
+            })
+            .collect();
+        let capture_offsets = captured
+            .keys()
+            .map(|&(level, offset)| context.calculate_param_offset(level, offset))
+            .collect::<Option<Rc<[_]>>>()?;
+
+        let mut fragment = Code::fragment_pool();
+        let root = pool.copy_into(body_idx, &mut fragment, |l, offset| {
+            if l <= level {
+                let pos = captured.keys().position(|&c| c == (l, offset.0));
+                (1, ParamIdx(pos.unwrap_or_default()))
+            } else {
+                (l - level, offset)
+            }
+        });
+        let params = captured
+            .values()
+            .map(|&name| fragment.intern_string(pool.get_string(name)))
+            .collect();
+
+        let mut globals = Globals::default();
+        for (&name_idx, &fun_idx) in &self.function_defs {
+            let name = pool.get_string(name_idx).to_string();
+            globals
+                .functions
+                .insert(name, self.functions[fun_idx.0].clone());
+        }
+        for (&name_idx, slot) in &self.constants {
+            let name = pool.get_string(name_idx).to_string();
+            globals.constants.insert(name, slot.clone());
+        }
+
+        let code = Code::new(fragment, root, params, Vec::new(), Rc::new(globals));
+        context.alloc(1);
 
-                let result = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
-                mem.truncate(start_len);
-                mem.push(result);
+        Some(CompiledFunction::new(
+            move |mem: &mut Vec<Value>| {
+                let env = read_captures(mem, &capture_offsets)?;
+                mem.push(Value::Code(code.with_env(env)));
                 Ok(())
             },
             0,
@@ -247,19 +505,14 @@ impl CompiledFunctions {
         param_count: usize,
         body_idx: AstIdx,
     ) -> Option<CompiledFunction> {
-        //let initial_stack_depth = context.stack_depth();
-
-        context.enter_scope(param_count);
-        let curr_lev = context.frames.len();
-        let captured_vars: Rc<[_]> = self
-            .find_captured_vars(node, pool, curr_lev)
-            .into_iter()
-            .map(|(level, offset)| {
-                let id = context.calculate_param_offset(level, offset);
-                id
-            })
-            .collect();
+        let lambda_level = context.frames.len() + 1;
+        let captured_vars: Rc<[_]> = self.find_captured_vars(node, pool, lambda_level).into();
+        let capture_offsets = captured_vars
+            .iter()
+            .map(|&(level, offset)| context.calculate_param_offset(level, offset))
+            .collect::<Option<Rc<[_]>>>()?;
 
+        context.enter_scope(captured_vars);
         let body_func = self.compile_expr(body_idx, pool, context)?;
         context.exit_scope();
 
@@ -267,11 +520,7 @@ impl CompiledFunctions {
 
         Some(CompiledFunction::new(
             move |mem: &mut Vec<Value>| {
-                let len = mem.len() - 1;
-                let captures: Box<[_]> = captured_vars
-                    .into_iter()
-                    .map(|i| mem[len - *i].clone())
-                    .collect();
+                let captures: Box<[_]> = read_captures(mem, &capture_offsets)?.into();
 
                 println!("Lambda @ CAPTURES: {:?}", &captures);
 
@@ -280,7 +529,7 @@ impl CompiledFunctions {
                     move |mem: &mut Vec<Value>| {
                         let start_len = mem.len();
                         mem.extend(captures.clone());
-                        body_func.clone().call(mem)?;
+                        body_func.clone().call_body(mem)?;
                         let result = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
                         mem.truncate(start_len);
                         mem.push(result);
@@ -303,16 +552,20 @@ impl CompiledFunctions {
         context: &mut CompilationContext,
         body_idx: AstIdx,
         param_count: usize,
+        variadic: bool,
     ) -> Option<CompiledFunction> {
-        context.enter_scope(param_count);
+        context.enter_scope(Rc::from([]));
 
         let body_lambda = self.compile_expr(body_idx, pool, context)?;
         context.exit_scope();
 
-        Some(CompiledFunction::new(
-            move |mem: &mut Vec<Value>| body_lambda.call(mem),
-            param_count,
-        ))
+        Some(
+            CompiledFunction::new(
+                move |mem: &mut Vec<Value>| body_lambda.call_body(mem),
+                param_count,
+            )
+            .with_variadic(variadic),
+        )
     }
 
     fn compile_user_func<'a>(
@@ -330,31 +583,79 @@ impl CompiledFunctions {
                 },
                 0,
             ))
+        } else if let Some(slot) = self.constants.get(&name_idx) {
+            let slot = slot.clone();
+            context.alloc(1);
+            Some(CompiledFunction::new(
+                move |mem: &mut Vec<Value>| {
+                    let value = slot
+                        .borrow()
+                        .clone()
+                        .ok_or(ErrTrace::new("constant used before it was evaluated"))?;
+                    mem.push(value);
+                    Ok(())
+                },
+                0,
+            ))
         } else {
             None
         }
     }
 
-    pub fn compile(&mut self, pool: &AstPool) {
+    /// Compile all functions of the pool and evaluate its constants, each
+    /// after the constants it depends on.
+    pub fn compile(&mut self, pool: &AstPool) -> Result<(), ErrTrace> {
+        for &name_idx in pool.const_defs.keys() {
+            self.constants.insert(name_idx, Rc::new(RefCell::new(None)));
+        }
+
         for (&name_idx, &ast_idx) in &pool.function_defs {
             let lambda_idx = FunIdx(self.functions.len());
             self.function_defs.insert(name_idx, lambda_idx);
-            let param_count = if let Ast::FunctionDef { param_count, .. } = pool[ast_idx] {
-                param_count
+            let (param_count, variadic) = if let Ast::FunctionDef {
+                param_count,
+                variadic,
+                ..
+            } = pool[ast_idx]
+            {
+                (param_count, variadic)
             } else {
-                0
+                (0, false)
             };
-            self.functions.push(CompiledFunction::new(
-                |mem: &mut Vec<Value>| {
-                    panic!("Function is not implemented yet");
-                },
-                param_count,
-            ));
+            let params = pool.function_params.get(&name_idx).into_iter().flatten();
+            let defaults: Rc<[CompiledFunction]> = params
+                .filter(|param| param.default.is_some())
+                .map(|_| CompiledFunction::new(not_compiled, 0))
+                .collect();
+            self.functions.push(
+                CompiledFunction::new(not_compiled, param_count)
+                    .with_variadic(variadic)
+                    .with_defaults(defaults),
+            );
         }
 
         for (&name_idx, &ast_idx) in &pool.function_defs {
             let lambda_idx = self.function_defs[&name_idx];
 
+            // Defaults compile like constants, they cannot use parameters
+            let params = pool.function_params.get(&name_idx).into_iter().flatten();
+            let default_exprs = params.filter_map(|param| param.default);
+            let defaults = self.functions[lambda_idx.0].defaults.clone();
+            for (default_idx, default) in default_exprs.zip(defaults.iter().flat_map(|d| d.iter()))
+            {
+                let mut ctx = CompilationContext::new();
+                let compiled = self
+                    .compile_expr(default_idx, pool, &mut ctx)
+                    .ok_or_else(|| {
+                        ErrTrace::new(format!(
+                            "failed to compile a default of '{}'",
+                            pool.get_string(name_idx)
+                        ))
+                    })?;
+                *default.inner.borrow_mut() =
+                    Box::new(move |mem: &mut Vec<Value>| compiled.call_body(mem));
+            }
+
             if let Ast::FunctionDef {
                 body_idx,
                 param_count,
@@ -364,16 +665,131 @@ impl CompiledFunctions {
                 let mut ctx = CompilationContext::new();
                 if let Some(compiled_body) = self.compile_expr(body_idx, pool, &mut ctx) {
                     let func_idx = lambda_idx.0;
-                    *self.functions[func_idx].inner.borrow_mut() =
-                        Box::new(move |mem: &mut Vec<Value>| compiled_body.call(mem));
+                    if pool.attribute(name_idx, "memo").is_some() {
+                        // Recursive calls go through the function, so they
+                        // are cached too
+                        let cache = self.memo_caches.entry(name_idx).or_default();
+                        *self.functions[func_idx].inner.borrow_mut() =
+                            Box::new(cache.wrap(param_count, move |mem: &mut Vec<Value>| {
+                                compiled_body.call_body(mem)
+                            }));
+                    } else {
+                        *self.functions[func_idx].inner.borrow_mut() =
+                            Box::new(move |mem: &mut Vec<Value>| compiled_body.call_body(mem));
+                    }
                 } else {
-                    eprintln!(
-                        "Failed to compile function body for {}",
+                    return Err(ErrTrace::new(format!(
+                        "failed to compile the body of '{}'",
                         pool.get_string(name_idx)
+                    )));
+                }
+            }
+        }
+
+        let order = pool.const_eval_order().map_err(|name_idx| {
+            ErrTrace::new(format!(
+                "constant '{}' depends on itself",
+                pool.get_string(name_idx)
+            ))
+        })?;
+
+        for name_idx in order {
+            let const_name = pool.get_string(name_idx);
+            let value = self
+                .evaluate_const(pool.const_defs[&name_idx], pool)
+                .map_err(|e| e.wrap(format!("while evaluating constant '{}'", const_name)))?;
+            *self.constants[&name_idx].borrow_mut() = Some(value);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate the `comptime` blocks of the program that are not evaluated
+    /// yet and inline their values, after compiling the program they can
+    /// call. The definitions with such blocks are replaced by copies holding
+    /// the literals.
+    pub fn resolve_comptime(pool: &mut AstPool) -> Result<(), ErrTrace> {
+        let defs: Vec<(NameIdx, AstIdx)> = pool
+            .function_defs
+            .iter()
+            .chain(&pool.const_defs)
+            .map(|(&name_idx, &def_idx)| (name_idx, def_idx))
+            .filter(|&(_, def_idx)| !pool.comptime_calls(def_idx, 1).is_empty())
+            .collect();
+        if defs.is_empty() {
+            return Ok(());
+        }
+
+        let mut program = CompiledFunctions::new();
+        program.compile(pool)?;
+
+        for (name_idx, def_idx) in defs {
+            let mut values = HashMap::new();
+            for (call_idx, level) in pool.comptime_calls(def_idx, 1) {
+                let location = match pool.comptime_blocks.get(&call_idx) {
+                    Some(block) => format!(
+                        "in the comptime block at line {}, column {}",
+                        block.line, block.column
+                    ),
+                    None => "in a comptime block".to_string(),
+                };
+                let value = program
+                    .evaluate_comptime(pool, call_idx, level)
+                    .map_err(|e| e.wrap(&location))?;
+                values.insert(call_idx, (value, location));
+            }
+
+            let mut unsupported = None;
+            let new_def_idx = pool.inline_literals(def_idx, &mut |pool, call_idx| {
+                let (value, location) = values.get(&call_idx)?;
+                let literal = add_literal(pool, value);
+                if literal.is_none() {
+                    unsupported = Some(
+                        ErrTrace::new(format!(
+                            "the value {:?} cannot be written as a literal",
+                            value
+                        ))
+                        .wrap(location),
                     );
                 }
+                literal
+            });
+            if let Some(e) = unsupported {
+                return Err(e);
             }
+
+            match pool.function_defs.get_mut(&name_idx) {
+                Some(def_idx) => *def_idx = new_def_idx,
+                None => {
+                    pool.const_defs.insert(name_idx, new_def_idx);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate a `comptime` block written in a scope of `level`, which
+    /// uses none of the parameters of the scope
+    fn evaluate_comptime(
+        &self,
+        pool: &AstPool,
+        call_idx: AstIdx,
+        level: usize,
+    ) -> Result<Value, ErrTrace> {
+        let mut ctx = CompilationContext::new();
+        for _ in 1..level {
+            ctx.enter_scope(Rc::from([]));
         }
+        let compiled = self
+            .compile_expr(call_idx, pool, &mut ctx)
+            .ok_or(ErrTrace::new("failed to compile the block"))?;
+
+        let mut memory = Vec::new();
+        function::with_step_limit(COMPTIME_STEP_LIMIT, COMPTIME_DEPTH_LIMIT, || {
+            compiled.call(&mut memory)
+        })?;
+        memory.pop().ok_or(ErrTrace::new("stack underflow"))
     }
 
     fn debug_print_ast(&self, node_idx: AstIdx, pool: &AstPool, indent: usize) {
@@ -385,6 +801,23 @@ impl CompiledFunctions {
             }
         }
     }
+
+    /// Evaluate a constant definition, whose body compiles like the body of
+    /// a function without parameters
+    fn evaluate_const(&self, def_idx: AstIdx, pool: &AstPool) -> Result<Value, ErrTrace> {
+        let Ast::ConstDef { body_idx, .. } = pool[def_idx] else {
+            return Err(ErrTrace::new("not a constant definition"));
+        };
+        let mut ctx = CompilationContext::new();
+        let compiled_body = self
+            .compile_expr(body_idx, pool, &mut ctx)
+            .ok_or(ErrTrace::new("failed to compile expression"))?;
+
+        let mut memory = Vec::new();
+        compiled_body.call_body(&mut memory)?;
+        memory.pop().ok_or(ErrTrace::new("stack underflow"))
+    }
+
     pub fn execute(&self, expr_idx: AstIdx, pool: &AstPool) -> Option<Value> {
         println!("Full AST structure:");
         self.debug_print_ast(expr_idx, pool, 0);
@@ -392,7 +825,7 @@ impl CompiledFunctions {
         let mut ctx = CompilationContext::new();
         if let Some(compiled_expr) = self.compile_expr(expr_idx, pool, &mut ctx) {
             let mut memory = Vec::new();
-            if let Err(e) = compiled_expr.call(&mut memory) {
+            if let Err(e) = compiled_expr.call_body(&mut memory) {
                 eprintln!("Error during execution: {:?}", e);
                 return None;
             }
@@ -403,6 +836,119 @@ impl CompiledFunctions {
     }
 }
 
+/// Run a code value, compiled like the body of a function taking the values
+/// of its captured parameters
+fn eval_code(code: &Code, mem: &mut Vec<Value>) -> Result<(), ErrTrace> {
+    let pool = code.pool();
+    let mut program = CompiledFunctions::new();
+    for (name, function) in &code.globals().functions {
+        if let Some(name_idx) = pool.get_name_idx_from_func(name) {
+            program
+                .function_defs
+                .insert(name_idx, FunIdx(program.functions.len()));
+            program.functions.push(function.clone());
+        }
+    }
+    for (name, slot) in &code.globals().constants {
+        if let Some(name_idx) = pool.get_name_idx_from_func(name) {
+            program.constants.insert(name_idx, slot.clone());
+        }
+    }
+
+    let env = code.env();
+    let mut ctx = CompilationContext::new();
+    let body = program
+        .compile_expr(code.root(), pool, &mut ctx)
+        .ok_or(ErrTrace::new("`eval` could not compile the code"))?;
+    let compiled =
+        CompiledFunction::new(move |mem: &mut Vec<Value>| body.call_body(mem), env.len());
+
+    mem.extend(env.iter().rev().cloned());
+    compiled.apply(mem, env.len())
+}
+
+/// Append the nodes of an expression building `value`, if it has one
+fn add_literal(pool: &mut AstPool, value: &Value) -> Option<AstIdx> {
+    match value {
+        Value::Int(i) => Some(pool.add_integer(*i)),
+        Value::Array(array) => add_collection(
+            pool,
+            crate::ast::PrimitiveFunc::ArrayNew,
+            array.as_slice().iter(),
+        ),
+        Value::List(items) => {
+            let array_idx =
+                add_collection(pool, crate::ast::PrimitiveFunc::ArrayNew, items.iter())?;
+            let total_len = pool.len(array_idx) + 1;
+            let func_idx = pool.add_primitive_func(crate::ast::PrimitiveFunc::SeqCollect);
+            Some(pool.add_call(func_idx, array_idx, 1, total_len))
+        }
+        Value::Map(map) => add_collection(
+            pool,
+            crate::ast::PrimitiveFunc::MapNew,
+            map.entries()
+                .into_iter()
+                .flat_map(|(key, value)| [key, value]),
+        ),
+        _ => None,
+    }
+}
+
+/// Append a call of `func` with the literals of `items`, like the ones of
+/// `#[..]` and `#{..}`
+fn add_collection<'v>(
+    pool: &mut AstPool,
+    func: crate::ast::PrimitiveFunc,
+    items: impl Iterator<Item = &'v Value>,
+) -> Option<AstIdx> {
+    let start = pool.nodes.len();
+    let mut child_start = None;
+    let mut child_count = 0;
+    for item in items {
+        child_start = Some(add_literal(pool, item)?);
+        child_count += 1;
+    }
+
+    let func_idx = pool.add_primitive_func(func);
+    let total_len = pool.nodes.len() - start;
+    Some(pool.add_call(
+        func_idx,
+        child_start.unwrap_or(func_idx),
+        child_count,
+        total_len,
+    ))
+}
+
+fn pop_code(mem: &mut Vec<Value>, func_name: &str) -> Result<Code, ErrTrace> {
+    match pop_arg(mem)? {
+        Value::Code(code) => Ok(code),
+        other => Err(ErrTrace::new(format!(
+            "`{}` expects code but got {:?}",
+            func_name, other
+        ))),
+    }
+}
+
+/// For each parameter of the callee of a call with named arguments, the
+/// index of the written argument it takes, `None` for those left to their
+/// default
+fn named_arg_order(named_args: &NamedArgs, pool: &AstPool) -> Option<Vec<Option<usize>>> {
+    let params = pool.function_params.get(&named_args.callee)?;
+    let mut order: Vec<Option<usize>> = (0..params.len())
+        .map(|i| (i < named_args.positional).then_some(i))
+        .collect();
+    for (i, name) in named_args.names.iter().enumerate() {
+        let index = params.iter().position(|param| param.name == *name)?;
+        order[index] = Some(named_args.positional + i);
+    }
+    Some(order)
+}
+
+/// Placeholder body of the functions of the program until they are compiled
+fn not_compiled(_: &mut Vec<Value>) -> Result<(), ErrTrace> {
+    Err(ErrTrace::new("function is not compiled yet"))
+}
+
 fn compile_integer(context: &mut CompilationContext, i: i64) -> Option<CompiledFunction> {
     context.alloc(1);
 
@@ -415,12 +961,25 @@ fn compile_integer(context: &mut CompilationContext, i: i64) -> Option<CompiledF
     ))
 }
 
+fn compile_effect(context: &mut CompilationContext, name: &str) -> Option<CompiledFunction> {
+    context.alloc(1);
+    let name: Rc<str> = Rc::from(name);
+
+    Some(CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            mem.push(Value::Effect(name.clone()));
+            Ok(())
+        },
+        0,
+    ))
+}
+
 fn compile_param(
     context: &mut CompilationContext,
     offset: crate::ParamIdx,
     level: usize,
 ) -> Option<CompiledFunction> {
-    let param_index = context.calculate_param_offset(level, offset.0);
+    let param_index = context.calculate_param_offset(level, offset.0)?;
     context.alloc(1);
 
     Some(CompiledFunction::new(
@@ -455,47 +1014,580 @@ fn compile_primitive_func(
 ) -> Option<CompiledFunction> {
     context.alloc(1);
     match primitive_func {
-        crate::ast::PrimitiveFunc::Add => Some(CompiledFunction::new(
-            move |mem: &mut Vec<Value>| {
-                let fun = CompiledFunction::new(
-                    move |mem: &mut Vec<Value>| {
-                        let b = mem.pop().unwrap();
-                        let a = mem.pop().unwrap();
-                        if let (Value::Int(a_val), Value::Int(b_val)) = (a, b) {
-                            mem.push(Value::Int(a_val + b_val));
-                            Ok(())
-                        } else {
-                            Err(ErrTrace::new("Wrong Argument type for `add` "))
-                        }
-                    },
-                    2,
-                );
+        crate::ast::PrimitiveFunc::Add => primitive(2, |mem: &mut Vec<Value>| {
+            let b = pop_arg(mem)?;
+            let a = pop_arg(mem)?;
+            if let (Value::Int(a_val), Value::Int(b_val)) = (a, b) {
+                mem.push(Value::Int(a_val + b_val));
+                Ok(())
+            } else {
+                Err(ErrTrace::new("Wrong Argument type for `add` "))
+            }
+        }),
+        crate::ast::PrimitiveFunc::Multiply => primitive(2, |mem: &mut Vec<Value>| {
+            let b = pop_arg(mem)?;
+            let a = pop_arg(mem)?;
+            if let (Value::Int(a_val), Value::Int(b_val)) = (a, b) {
+                mem.push(Value::Int(a_val * b_val));
+                Ok(())
+            } else {
+                Err(ErrTrace::new("Wrong Argument type for `mul` "))
+            }
+        }),
+        crate::ast::PrimitiveFunc::Pipe => primitive(2, |mem: &mut Vec<Value>| {
+            let value = pop_arg(mem)?;
+            match pop_arg(mem)? {
+                Value::Fun(func) => {
+                    mem.push(value);
+                    func.apply(mem, 1)
+                }
+                other => Err(ErrTrace::new(format!(
+                    "`|>` expects a function on its right but got {:?}",
+                    other
+                ))),
+            }
+        }),
+        crate::ast::PrimitiveFunc::Compose => primitive(2, |mem: &mut Vec<Value>| {
+            let first = pop_arg(mem)?;
+            let second = pop_arg(mem)?;
+            let (Value::Fun(first), Value::Fun(second)) = (first, second) else {
+                return Err(ErrTrace::new("`>>` expects functions on both sides"));
+            };
+
+            let param_count = first.param_count;
+            let composed = CompiledFunction::new(
+                move |mem: &mut Vec<Value>| {
+                    first.apply(mem, param_count)?;
+                    second.apply(mem, 1)
+                },
+                param_count,
+            );
+            mem.push(Value::Fun(composed));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Fold => primitive(3, |mem: &mut Vec<Value>| {
+            const EXPECTED: &str = "`fold` expects a list, an array or a sequence and a function";
+            let mut seq = match pop_arg(mem)? {
+                items @ (Value::List(_) | Value::Array(_) | Value::Seq(_)) => {
+                    Seq::from_value(items)?
+                }
+                _ => return Err(ErrTrace::new(EXPECTED)),
+            };
+            let mut acc = pop_arg(mem)?;
+            let Value::Fun(func) = pop_arg(mem)? else {
+                return Err(ErrTrace::new(EXPECTED));
+            };
 
-                mem.push(Value::Fun(fun));
+            while let Some((item, rest)) = seq.next()? {
+                mem.push(item);
+                mem.push(acc);
+                func.apply(mem, 2)?;
+                acc = pop_arg(mem)?;
+                seq = rest;
+            }
+            mem.push(acc);
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::OptionSome => primitive(1, |mem: &mut Vec<Value>| {
+            let value = pop_arg(mem)?;
+            mem.push(Value::Option(Some(Rc::new(value))));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::OptionNone => Some(CompiledFunction::new(
+            |mem: &mut Vec<Value>| {
+                mem.push(Value::Option(None));
                 Ok(())
             },
-            2,
+            0,
         )),
-        crate::ast::PrimitiveFunc::Multiply => Some(CompiledFunction::new(
-            move |mem: &mut Vec<Value>| {
-                let fun = CompiledFunction::new(
-                    move |mem: &mut Vec<Value>| {
-                        let b = mem.pop().unwrap();
-                        let a = mem.pop().unwrap();
-                        if let (Value::Int(a_val), Value::Int(b_val)) = (a, b) {
-                            mem.push(Value::Int(a_val * b_val));
-                            Ok(())
-                        } else {
-                            Err(ErrTrace::new("Wrong Argument type for `mul` "))
-                        }
-                    },
-                    2,
-                );
+        crate::ast::PrimitiveFunc::ResultOk => primitive(1, |mem: &mut Vec<Value>| {
+            let value = pop_arg(mem)?;
+            mem.push(Value::Result(Ok(Rc::new(value))));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::ResultErr => primitive(1, |mem: &mut Vec<Value>| {
+            let error = pop_arg(mem)?;
+            mem.push(Value::Result(Err(Rc::new(error))));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Raise => primitive(1, |mem: &mut Vec<Value>| {
+            Err(ErrTrace::raise(pop_arg(mem)?))
+        }),
+        crate::ast::PrimitiveFunc::ErrorMessage => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Error(error) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`message` expects a caught error"));
+            };
+            mem.push(Value::Str(error.describe().into()));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::ErrorPayload => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Error(error) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`payload` expects a caught error"));
+            };
+            let payload = error.payload().cloned().map(Rc::new);
+            mem.push(Value::Option(payload));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::RefNew => primitive(1, |mem: &mut Vec<Value>| {
+            continuation::check_not_replaying("make a reference")?;
+            let value = pop_arg(mem)?;
+            mem.push(Value::Ref(Rc::new(RefCell::new(value))));
+            Ok(())
+        }),
+        // A map gives a function of the key, so `get(m, k)` is an
+        // over-application
+        crate::ast::PrimitiveFunc::RefGet => primitive(1, |mem: &mut Vec<Value>| {
+            match pop_arg(mem)? {
+                Value::Ref(cell) => {
+                    continuation::check_not_replaying("read a reference")?;
+                    let value = cell.borrow().clone();
+                    mem.push(value);
+                }
+                Value::Map(map) => {
+                    let lookup = move |mem: &mut Vec<Value>| {
+                        let value = map.get(&pop_arg(mem)?)?.cloned().map(Rc::new);
+                        mem.push(Value::Option(value));
+                        Ok(())
+                    };
+                    mem.push(Value::Fun(CompiledFunction::new(lookup, 1)));
+                }
+                other => {
+                    return Err(ErrTrace::new(format!(
+                        "`get` expects a reference or a map but got {:?}",
+                        other
+                    )));
+                }
+            }
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::RefSet => primitive(2, |mem: &mut Vec<Value>| {
+            let Value::Ref(cell) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`set` expects a reference"));
+            };
+            continuation::check_not_replaying("set a reference")?;
+            *cell.borrow_mut() = pop_arg(mem)?;
+            mem.push(Value::Unit);
+            Ok(())
+        }),
+        // Called with any number of items, which arrive as a list
+        crate::ast::PrimitiveFunc::ArrayNew => variadic_primitive(1, |mem: &mut Vec<Value>| {
+            let Value::List(items) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`array` expects its items as a list"));
+            };
+            function::charge_steps(items.len() as u64)?;
+            mem.push(Value::Array(Array::new(items.to_vec())));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::ArrayIndex => primitive(2, |mem: &mut Vec<Value>| {
+            let (Value::Array(array), Value::Int(index)) = (pop_arg(mem)?, pop_arg(mem)?) else {
+                return Err(ErrTrace::new("`index` expects an array and an Int"));
+            };
+            let item = array_position(index, array.len())
+                .and_then(|index| array.get(index))
+                .ok_or_else(|| {
+                    ErrTrace::new(format!(
+                        "index {} out of bounds for array of length {}",
+                        index,
+                        array.len()
+                    ))
+                })?;
+            mem.push(item.clone());
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Len => primitive(1, |mem: &mut Vec<Value>| {
+            let len = match pop_arg(mem)? {
+                Value::Array(array) => array.len(),
+                Value::List(items) => items.len(),
+                Value::Str(s) => s.chars().count(),
+                Value::Map(map) => map.len(),
+                other => {
+                    return Err(ErrTrace::new(format!(
+                        "`len` expects an array, a list, a string or a map but got {:?}",
+                        other
+                    )));
+                }
+            };
+            mem.push(Value::Int(len as i64));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Slice => primitive(3, |mem: &mut Vec<Value>| {
+            let (Value::Array(array), Value::Int(from), Value::Int(to)) =
+                (pop_arg(mem)?, pop_arg(mem)?, pop_arg(mem)?)
+            else {
+                return Err(ErrTrace::new("`slice` expects an array and two Ints"));
+            };
+            let len = array.len();
+            let slice = array_position(from, len + 1)
+                .zip(array_position(to, len + 1))
+                .and_then(|(from, to)| array.slice(from, to))
+                .ok_or_else(|| {
+                    ErrTrace::new(format!(
+                        "slice {}..{} out of bounds for array of length {}",
+                        from, to, len
+                    ))
+                })?;
+            mem.push(Value::Array(slice));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Push => primitive(2, |mem: &mut Vec<Value>| {
+            let Value::Array(array) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`push` expects an array"));
+            };
+            let value = pop_arg(mem)?;
+            mem.push(Value::Array(array.push(value)));
+            Ok(())
+        }),
+        // Called with keys and values alternating, which arrive as a list
+        crate::ast::PrimitiveFunc::MapNew => variadic_primitive(1, |mem: &mut Vec<Value>| {
+            let Value::List(items) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`map_of` expects its entries as a list"));
+            };
+            if items.len() % 2 != 0 {
+                return Err(ErrTrace::new("`map_of` expects a value for every key"));
+            }
+            function::charge_steps(items.len() as u64 / 2)?;
+
+            let mut map = Map::default();
+            for entry in items.chunks(2) {
+                map = map.insert(entry[0].clone(), entry[1].clone())?;
+            }
+            mem.push(Value::Map(map));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::MapInsert => primitive(3, |mem: &mut Vec<Value>| {
+            let Value::Map(map) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`insert` expects a map"));
+            };
+            let key = pop_arg(mem)?;
+            let value = pop_arg(mem)?;
+            mem.push(Value::Map(map.insert(key, value)?));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::MapRemove => primitive(2, |mem: &mut Vec<Value>| {
+            let Value::Map(map) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`remove` expects a map"));
+            };
+            let key = pop_arg(mem)?;
+            mem.push(Value::Map(map.remove(&key)?));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::MapKeys => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Map(map) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`keys` expects a map"));
+            };
+            function::charge_steps(map.len() as u64)?;
+            let keys: Vec<Value> = map.entries().into_iter().map(|(k, _)| k.clone()).collect();
+            mem.push(Value::List(Rc::from(keys)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::MapValues => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Map(map) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`values` expects a map"));
+            };
+            function::charge_steps(map.len() as u64)?;
+            let values: Vec<Value> = map.entries().into_iter().map(|(_, v)| v.clone()).collect();
+            mem.push(Value::List(Rc::from(values)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::MapContains => primitive(2, |mem: &mut Vec<Value>| {
+            let Value::Map(map) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`contains` expects a map"));
+            };
+            let contains = map.contains(&pop_arg(mem)?)?;
+            mem.push(Value::Bool(contains));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Range => primitive(2, |mem: &mut Vec<Value>| {
+            let (Value::Int(from), Value::Int(to)) = (pop_arg(mem)?, pop_arg(mem)?) else {
+                return Err(ErrTrace::new("`..` expects two Ints"));
+            };
+            mem.push(Value::Seq(Seq::range(from, to, false)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::RangeInclusive => primitive(2, |mem: &mut Vec<Value>| {
+            let (Value::Int(from), Value::Int(to)) = (pop_arg(mem)?, pop_arg(mem)?) else {
+                return Err(ErrTrace::new("`..=` expects two Ints"));
+            };
+            mem.push(Value::Seq(Seq::range(from, to, true)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::SeqTake => primitive(2, |mem: &mut Vec<Value>| {
+            let seq = pop_seq(mem, "take")?;
+            let n = pop_count(mem, "take")?;
+            mem.push(Value::Seq(seq.take(n)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::SeqDrop => primitive(2, |mem: &mut Vec<Value>| {
+            let seq = pop_seq(mem, "drop")?;
+            let n = pop_count(mem, "drop")?;
+            mem.push(Value::Seq(seq.drop(n)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::SeqMap => primitive(2, |mem: &mut Vec<Value>| {
+            let seq = pop_seq(mem, "map")?;
+            let Value::Fun(f) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`map` expects a function"));
+            };
+            mem.push(Value::Seq(seq.map(f)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::SeqFilter => primitive(2, |mem: &mut Vec<Value>| {
+            let seq = pop_seq(mem, "filter")?;
+            let Value::Fun(f) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`filter` expects a function"));
+            };
+            mem.push(Value::Seq(seq.filter(f)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::SeqZip => primitive(2, |mem: &mut Vec<Value>| {
+            let first = pop_seq(mem, "zip")?;
+            let second = pop_seq(mem, "zip")?;
+            mem.push(Value::Seq(first.zip(second)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::SeqCollect => primitive(1, |mem: &mut Vec<Value>| {
+            let items = pop_seq(mem, "collect")?.collect()?;
+            mem.push(Value::List(Rc::from(items)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Lazy => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Fun(compute) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`lazy` expects a function"));
+            };
+            mem.push(Value::Thunk(Thunk::new(compute)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Force => primitive(1, |mem: &mut Vec<Value>| {
+            let value = match pop_arg(mem)? {
+                Value::Thunk(thunk) => thunk.force()?,
+                other => other,
+            };
+            mem.push(value);
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Yield => primitive(2, |mem: &mut Vec<Value>| {
+            let head = pop_arg(mem)?;
+            let Value::Fun(rest) = pop_arg(mem)? else {
+                return Err(ErrTrace::new(
+                    "`yield` expects a function computing the rest",
+                ));
+            };
+            mem.push(Value::Seq(Seq::cons(head, Thunk::new(rest))));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::SeqNext => primitive(1, |mem: &mut Vec<Value>| {
+            let step = pop_seq(mem, "next")?.next()?.map(|(item, rest)| {
+                let pair = Array::new(vec![item, Value::Seq(rest)]);
+                Rc::new(Value::Array(pair))
+            });
+            mem.push(Value::Option(step));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Reset => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Fun(body) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`reset` expects a function"));
+            };
+            continuation::reset(body, mem)
+        }),
+        crate::ast::PrimitiveFunc::Shift => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Fun(f) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`shift` expects a function"));
+            };
+            continuation::shift(f, mem)
+        }),
+        crate::ast::PrimitiveFunc::CallCc => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Fun(f) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`callcc` expects a function"));
+            };
+            continuation::callcc(f, mem)
+        }),
+        // Called with an effect and any number of arguments for it
+        crate::ast::PrimitiveFunc::Perform => variadic_primitive(2, |mem: &mut Vec<Value>| {
+            let (Value::Effect(effect), Value::List(args)) = (pop_arg(mem)?, pop_arg(mem)?) else {
+                return Err(ErrTrace::new("`perform` expects an effect"));
+            };
+            continuation::perform(effect, args, mem)
+        }),
+        // Called with the body, then effects and their handlers alternating
+        crate::ast::PrimitiveFunc::Handle => variadic_primitive(2, |mem: &mut Vec<Value>| {
+            let (Value::Fun(body), Value::List(items)) = (pop_arg(mem)?, pop_arg(mem)?) else {
+                return Err(ErrTrace::new("`handle` expects a function"));
+            };
 
-                mem.push(Value::Fun(fun));
+            let mut clauses = Vec::new();
+            for clause in items.chunks(2) {
+                let [Value::Effect(effect), Value::Fun(handler)] = clause else {
+                    return Err(ErrTrace::new(
+                        "`handle` expects an effect followed by its handler",
+                    ));
+                };
+                clauses.push((effect.clone(), handler.clone()));
+            }
+            continuation::handle(body, Rc::from(clauses), mem)
+        }),
+        // Quotes are compiled by `compile_quote`, `quote` is not a value
+        crate::ast::PrimitiveFunc::Quote => primitive(1, |_: &mut Vec<Value>| {
+            Err(ErrTrace::new("`quote` must be written as `quote { e }`"))
+        }),
+        crate::ast::PrimitiveFunc::Eval => primitive(1, |mem: &mut Vec<Value>| {
+            let code = pop_code(mem, "eval")?;
+            eval_code(&code, mem)
+        }),
+        crate::ast::PrimitiveFunc::CodeKind => primitive(1, |mem: &mut Vec<Value>| {
+            let code = pop_code(mem, "code_kind")?;
+            mem.push(Value::Str(Rc::from(code.kind())));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::CodeParts => primitive(1, |mem: &mut Vec<Value>| {
+            let parts = pop_code(mem, "code_parts")?.parts();
+            mem.push(Value::List(parts.into_iter().map(Value::Code).collect()));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::CodeCall => primitive(2, |mem: &mut Vec<Value>| {
+            let callee = pop_code(mem, "code_call")?;
+            let list = pop_arg(mem)?;
+            let items = match &list {
+                Value::List(items) => &items[..],
+                Value::Array(array) => array.as_slice(),
+                _ => return Err(ErrTrace::new("`code_call` expects a list of arguments")),
+            };
+            let args = items
+                .iter()
+                .map(|item| match item {
+                    Value::Code(code) => Ok(code.clone()),
+                    other => Err(ErrTrace::new(format!(
+                        "`code_call` expects code arguments but got {:?}",
+                        other
+                    ))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            mem.push(Value::Code(Code::call(&callee, &args)));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Lift => primitive(1, |mem: &mut Vec<Value>| {
+            let code = match pop_arg(mem)? {
+                Value::Int(i) => Code::int(i),
+                Value::Code(code) => code,
+                other => {
+                    return Err(ErrTrace::new(format!(
+                        "`lift` expects an integer but got {:?}",
+                        other
+                    )));
+                }
+            };
+            mem.push(Value::Code(code));
+            Ok(())
+        }),
+        // Blocks are evaluated when the program is loaded, except in code
+        // that is not part of it like quoted code
+        crate::ast::PrimitiveFunc::Comptime => primitive(1, |mem: &mut Vec<Value>| {
+            let Value::Fun(body) = pop_arg(mem)? else {
+                return Err(ErrTrace::new("`comptime` expects a function"));
+            };
+            body.apply(mem, 0)
+        }),
+        crate::ast::PrimitiveFunc::Equal => primitive(2, |mem: &mut Vec<Value>| {
+            let a = pop_arg(mem)?;
+            let b = pop_arg(mem)?;
+            let equal = a.equals(&b)?;
+            mem.push(Value::Bool(equal));
+            Ok(())
+        }),
+        crate::ast::PrimitiveFunc::Less => primitive(2, |mem: &mut Vec<Value>| {
+            let a = pop_arg(mem)?;
+            let b = pop_arg(mem)?;
+            if let (Value::Int(a_val), Value::Int(b_val)) = (a, b) {
+                mem.push(Value::Bool(a_val < b_val));
                 Ok(())
-            },
-            2,
-        )),
+            } else {
+                Err(ErrTrace::new("Wrong Argument type for `less` "))
+            }
+        }),
+        crate::ast::PrimitiveFunc::Greater => primitive(2, |mem: &mut Vec<Value>| {
+            let a = pop_arg(mem)?;
+            let b = pop_arg(mem)?;
+            if let (Value::Int(a_val), Value::Int(b_val)) = (a, b) {
+                mem.push(Value::Bool(a_val > b_val));
+                Ok(())
+            } else {
+                Err(ErrTrace::new("Wrong Argument type for `greater` "))
+            }
+        }),
+    }
+}
+
+/// Push a primitive as a function value. `f` finds its arguments on top of
+/// the stack, the first one topmost, and replaces them with its result.
+fn primitive<F>(param_count: usize, f: F) -> Option<CompiledFunction>
+where
+    F: for<'a> Fn(&'a mut Vec<Value>) -> Result<(), ErrTrace> + 'static,
+{
+    let fun = CompiledFunction::new(f, param_count);
+    Some(CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            mem.push(Value::Fun(fun.clone()));
+            Ok(())
+        },
+        0,
+    ))
+}
+
+/// Push a primitive whose last parameter collects the remaining arguments
+/// into a list, like [`primitive`].
+fn variadic_primitive<F>(param_count: usize, f: F) -> Option<CompiledFunction>
+where
+    F: for<'a> Fn(&'a mut Vec<Value>) -> Result<(), ErrTrace> + 'static,
+{
+    let fun = CompiledFunction::new(f, param_count).with_variadic(true);
+    Some(CompiledFunction::new(
+        move |mem: &mut Vec<Value>| {
+            mem.push(Value::Fun(fun.clone()));
+            Ok(())
+        },
+        0,
+    ))
+}
+
+/// `index` as a position below `len`
+fn array_position(index: i64, len: usize) -> Option<usize> {
+    usize::try_from(index).ok().filter(|&index| index < len)
+}
+
+fn pop_seq(mem: &mut Vec<Value>, func_name: &str) -> Result<Seq, ErrTrace> {
+    Seq::from_value(pop_arg(mem)?)
+        .map_err(|e| e.wrap(format!("in the arguments of `{}`", func_name)))
+}
+
+fn pop_count(mem: &mut Vec<Value>, func_name: &str) -> Result<usize, ErrTrace> {
+    match pop_arg(mem)? {
+        Value::Int(n) if n >= 0 => Ok(n as usize),
+        other => Err(ErrTrace::new(format!(
+            "`{}` expects a count of at least 0 but got {:?}",
+            func_name, other
+        ))),
     }
 }
+
+/// The values of captured variables, each at its distance from the top of
+/// the stack
+fn read_captures(mem: &[Value], offsets: &[usize]) -> Result<Vec<Value>, ErrTrace> {
+    offsets
+        .iter()
+        .map(|&offset| {
+            mem.len()
+                .checked_sub(offset + 1)
+                .map(|i| mem[i].clone())
+                .ok_or_else(|| {
+                    ErrTrace::new(format!(
+                        "captured variable out of bounds: offset {} but memory size {}",
+                        offset,
+                        mem.len()
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn pop_arg(mem: &mut Vec<Value>) -> Result<Value, ErrTrace> {
+    mem.pop().ok_or(ErrTrace::new("stack underflow"))
+}
@@ -1,11 +1,52 @@
 use crate::value::Value;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
+thread_local! {
+    /// Calls and elements left before evaluation is stopped, if it is bounded
+    static STEPS_LEFT: Cell<Option<u64>> = const { Cell::new(None) };
+    /// Calls that may still be nested, if evaluation is bounded
+    static DEPTH_LEFT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Run `f` with evaluation bounded to `limit` calls of compiled functions
+/// and elements walked by primitives, of which at most `max_depth` calls are
+/// nested, after which they fail. The depth bound keeps runaway recursion
+/// from overflowing the stack.
+pub fn with_step_limit<T>(limit: u64, max_depth: usize, f: impl FnOnce() -> T) -> T {
+    let outer_steps = STEPS_LEFT.replace(Some(limit));
+    let outer_depth = DEPTH_LEFT.replace(Some(max_depth));
+    let result = f();
+    STEPS_LEFT.set(outer_steps);
+    DEPTH_LEFT.set(outer_depth);
+    result
+}
+
+/// Spend `count` steps of a bounded evaluation, which primitives do for the
+/// elements they walk or build without calling a compiled function
+pub fn charge_steps(count: u64) -> Result<(), ErrTrace> {
+    match STEPS_LEFT.get() {
+        Some(steps) if steps < count => Err(ErrTrace::new("step limit exceeded")),
+        Some(steps) => {
+            STEPS_LEFT.set(Some(steps - count));
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ErrTrace {
     message: String,
     child: Option<Box<ErrTrace>>,
+    /// Value of an early return unwinding to the enclosing function body,
+    /// which is not an error
+    returning: Option<Box<Value>>,
+    /// Value passed to `raise`
+    payload: Option<Box<Value>>,
+    /// Value passed to a continuation, unwinding to the `callcc` or `reset`
+    /// with the given id
+    escaping: Option<(u64, Box<Value>)>,
 }
 
 impl ErrTrace {
@@ -13,13 +54,86 @@ impl ErrTrace {
         Self {
             message: message.into(),
             child: None,
+            returning: None,
+            payload: None,
+            escaping: None,
+        }
+    }
+
+    /// An error raised by the program with `raise(value)`
+    pub fn raise(value: Value) -> Self {
+        Self {
+            message: format!("raised {:?}", value),
+            child: None,
+            returning: None,
+            payload: Some(Box::new(value)),
+            escaping: None,
+        }
+    }
+
+    /// Return `value` from the enclosing function body, see
+    /// `CompiledFunction::call_body`
+    pub fn early_return(value: Value) -> Self {
+        Self {
+            message: "early return outside of a function".to_string(),
+            child: None,
+            returning: Some(Box::new(value)),
+            payload: None,
+            escaping: None,
+        }
+    }
+
+    /// Unwind to the `callcc` or `reset` with id `target`, passing `value`
+    pub fn escape(target: u64, value: Value) -> Self {
+        Self {
+            message: "continuation called outside of where it was captured".to_string(),
+            child: None,
+            returning: None,
+            payload: None,
+            escaping: Some((target, Box::new(value))),
+        }
+    }
+
+    /// The value of an escape to `target`, other errors are given back
+    pub fn escaped_to(self, target: u64) -> Result<Value, Self> {
+        match self.escaping {
+            Some((id, value)) if id == target => Ok(*value),
+            _ => Err(self),
+        }
+    }
+
+    /// Early returns and escapes unwind the stack without being errors
+    pub fn is_control_flow(&self) -> bool {
+        self.returning.is_some() || self.escaping.is_some()
+    }
+
+    /// The value given to `raise`, if the error was raised by the program
+    pub fn payload(&self) -> Option<&Value> {
+        match &self.payload {
+            Some(value) => Some(value),
+            None => self.child.as_ref()?.payload(),
+        }
+    }
+
+    /// The messages of the error and its causes, outermost first
+    pub fn describe(&self) -> String {
+        match &self.child {
+            Some(child) => format!("{}: {}", self.message, child.describe()),
+            None => self.message.clone(),
         }
     }
 
     pub fn wrap(self, message: impl Into<String>) -> Self {
+        if self.is_control_flow() {
+            return self;
+        }
+
         Self {
             message: message.into(),
             child: Some(Box::new(self)),
+            returning: None,
+            payload: None,
+            escaping: None,
         }
     }
 }
@@ -34,6 +148,13 @@ pub struct CompiledFunction {
 
     /// Number of parameters this function expects
     pub param_count: usize,
+
+    /// Whether the last parameter collects the remaining arguments into a list
+    pub variadic: bool,
+
+    /// Functions without parameters giving the values of the trailing
+    /// parameters that have a default, not counting the rest parameter
+    pub defaults: Option<Rc<[CompiledFunction]>>,
 }
 
 impl CompiledFunction {
@@ -45,19 +166,164 @@ impl CompiledFunction {
         CompiledFunction {
             inner: Rc::new(RefCell::new(Box::new(f))),
             param_count,
+            variadic: false,
+            defaults: None,
         }
     }
 
+    /// Make the last parameter collect the remaining arguments into a list
+    pub fn with_variadic(mut self, variadic: bool) -> Self {
+        self.variadic = variadic;
+        self
+    }
+
+    /// Evaluate the trailing parameters with the given functions when they
+    /// are left out of a call
+    pub fn with_defaults(mut self, defaults: Rc<[CompiledFunction]>) -> Self {
+        self.defaults = (!defaults.is_empty()).then_some(defaults);
+        self
+    }
+
+    /// The function giving the default of parameter `index`, if it has one
+    pub fn default_for(&self, index: usize) -> Option<&CompiledFunction> {
+        let defaults = self.defaults.as_ref()?;
+        let first = self.param_count - self.variadic as usize - defaults.len();
+        defaults.get(index.checked_sub(first)?)
+    }
+
     /// Call the function with the provided memory context and parameter base
     pub fn call(&self, mem: &mut Vec<Value>) -> Result<(), ErrTrace> {
+        charge_steps(1)?;
+        let depth = DEPTH_LEFT.get();
+        match depth {
+            Some(0) => return Err(ErrTrace::new("recursion too deep")),
+            Some(depth) => DEPTH_LEFT.set(Some(depth - 1)),
+            None => {}
+        }
+
         let closure = self.inner.borrow();
         //println!("\t\tbefore call :");
         //println!("\t\t\t\tmem     : {mem:?}");
         let ret = closure(mem);
+        DEPTH_LEFT.set(depth);
         //println!("\t\tafter  call :");
         //println!("\t\t\t\tmem     : {mem:?}");
         //println!("\t\t\t\tresult  : {ret:?}");
 
         ret
     }
+
+    /// Call a function body, turning an early return from within it into
+    /// its result
+    pub fn call_body(&self, mem: &mut Vec<Value>) -> Result<(), ErrTrace> {
+        let base = mem.len();
+        match self.call(mem) {
+            Err(ErrTrace {
+                returning: Some(value),
+                ..
+            }) => {
+                mem.truncate(base);
+                mem.push(*value);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Apply the function to the `argc` arguments on top of the stack and
+    /// replace them with the result.
+    ///
+    /// Fewer arguments than `param_count` produce a partially applied
+    /// function waiting for the rest, extra arguments are passed on to the
+    /// function returned by the saturated call.
+    pub fn apply(&self, mem: &mut Vec<Value>, mut argc: usize) -> Result<(), ErrTrace> {
+        if mem.len() < argc {
+            return Err(ErrTrace::new(format!(
+                "not enough values on the stack for call: expected {}, got {}",
+                argc,
+                mem.len()
+            )));
+        }
+
+        // Parameters left out of the call take their defaults when they all
+        // have one, their values go below the arguments with the last one
+        // deepest
+        let fixed = self.param_count - self.variadic as usize;
+        let default_count = self.defaults.as_ref().map_or(0, |defaults| defaults.len());
+        if argc < fixed && argc + default_count >= fixed {
+            let args: Vec<Value> = mem.drain(mem.len() - argc..).collect();
+            for index in (argc..fixed).rev() {
+                if let Some(default) = self.default_for(index) {
+                    default.call(mem)?;
+                }
+            }
+            mem.extend(args);
+            argc = fixed;
+        }
+
+        // The arguments for the rest parameter are the deepest ones, they
+        // are replaced by a single list in the order they were written
+        if self.variadic && argc + 1 >= self.param_count {
+            let rest_start = mem.len() - argc;
+            let rest_end = mem.len() + 1 - self.param_count;
+            let rest: Vec<Value> = mem.drain(rest_start..rest_end).rev().collect();
+            mem.insert(rest_start, Value::List(Rc::from(rest)));
+            argc = self.param_count;
+        }
+
+        if argc < self.param_count {
+            let args: Box<[Value]> = mem.drain(mem.len() - argc..).collect();
+            mem.push(Value::Fun(self.partial(args)));
+            return Ok(());
+        }
+
+        let base = mem.len() - self.param_count;
+        self.call(mem)?;
+        let result = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
+        mem.truncate(base);
+
+        let extra = argc - self.param_count;
+        if extra == 0 {
+            mem.push(result);
+            return Ok(());
+        }
+
+        match result {
+            Value::Fun(fun) => fun
+                .apply(mem, extra)
+                .map_err(|e| e.wrap("while applying extra arguments")),
+            other => Err(ErrTrace::new(format!(
+                "too many arguments: {} left over but the result {:?} is not a function",
+                extra, other
+            ))),
+        }
+    }
+
+    /// Bind the leading arguments of the function. `args` is laid out like
+    /// the stack, i.e. the first argument is the last element.
+    fn partial(&self, args: Box<[Value]>) -> CompiledFunction {
+        if args.is_empty() {
+            return self.clone();
+        }
+
+        let fun = self.clone();
+        let remaining = self.param_count - args.len();
+        let partial = CompiledFunction::new(
+            move |mem: &mut Vec<Value>| {
+                let start_len = mem.len();
+                mem.extend(args.iter().cloned());
+                fun.call(mem)?;
+                let result = mem.pop().ok_or(ErrTrace::new("stack underflow"))?;
+                mem.truncate(start_len);
+                mem.push(result);
+                Ok(())
+            },
+            remaining,
+        )
+        .with_variadic(self.variadic);
+        match &self.defaults {
+            Some(defaults) => partial.with_defaults(defaults.clone()),
+            None => partial,
+        }
+    }
 }
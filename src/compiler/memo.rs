@@ -0,0 +1,56 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::compiler::function::ErrTrace;
+use crate::value::Value;
+use crate::value::map::MapKey;
+
+/// Results of a `#[memo]` function by the values of its arguments, shared
+/// by all the copies of the function
+#[derive(Clone, Default)]
+pub struct MemoCache {
+    entries: Rc<RefCell<HashMap<MapKey, Value>>>,
+}
+
+impl MemoCache {
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+
+    /// Wrap the body of a function taking `param_count` arguments so that
+    /// it runs once for arguments with the same values. Calls with arguments
+    /// that cannot be hashed, like functions, are not cached.
+    pub fn wrap(
+        &self,
+        param_count: usize,
+        body: impl Fn(&mut Vec<Value>) -> Result<(), ErrTrace> + 'static,
+    ) -> impl Fn(&mut Vec<Value>) -> Result<(), ErrTrace> + 'static {
+        let entries = self.entries.clone();
+        move |mem: &mut Vec<Value>| {
+            let args = &mem[mem.len().saturating_sub(param_count)..];
+            let Ok(key) = MapKey::new(&Value::List(Rc::from(args))) else {
+                return body(mem);
+            };
+
+            let cached = entries.borrow().get(&key).cloned();
+            if let Some(result) = cached {
+                mem.push(result);
+                return Ok(());
+            }
+
+            body(mem)?;
+            let result = mem.last().ok_or(ErrTrace::new("stack underflow"))?;
+            entries.borrow_mut().insert(key, result.clone());
+            Ok(())
+        }
+    }
+}
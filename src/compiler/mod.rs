@@ -1,5 +1,7 @@
+pub mod continuation;
 pub mod executor;
 pub mod function;
+pub mod memo;
 
 // Re-export main types
 pub use self::executor::CompiledFunctions;
@@ -1,13 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{self, BufRead, Write};
 use std::path::Path;
 
 use crate::ast::pool::AstPool;
-use crate::ast::pretty_printer::PrettyPrinter;
+use crate::ast::pretty_printer::{PrettyPrinter, PrintConfig};
 use crate::ast::Ast;
 use crate::checker::type_check::TypeChecker;
+use crate::ast::indices::NameIdx;
 use crate::compiler::executor::{CompilationContext, CompiledFunctions};
+use crate::compiler::memo::MemoCache;
 use crate::parser::parser::parse_program;
 use crate::value::Value;
 
@@ -41,16 +43,34 @@ impl Interpreter {
             ));
         }
         let mut ret = String::new();
+        let mut saved = HashSet::new();
         for name in function_names {
             let deps = self.pool.find_dependencies(name);
             for dep in deps {
                 let dep_name = self.pool.get_string(dep);
                 let code = self.pretty_print_function(dep_name)?;
                 ret.extend(code.chars());
+                ret.push('\n');
+                saved.insert(dep);
             }
             let code = self.pretty_print_function(name)?;
             ret.extend(code.chars());
+            ret.push('\n');
+            saved.extend(self.pool.get_name_idx_from_func(name));
         }
+
+        // Keep the operators the saved functions can be written with
+        let printer = PrettyPrinter::new(&self.pool);
+        let operators = printer.print_operator_defs(|func_name| {
+            saved.contains(&func_name)
+                || self
+                    .pool
+                    .get_primitive_func(self.pool.get_string(func_name))
+                    .is_some()
+        });
+        ret.insert_str(0, &printer.print_macro_defs());
+        ret.insert_str(0, &operators);
+        ret.insert_str(0, &printer.print_effect_defs());
         match fs::write(path.as_ref(), ret) {
             Ok(_) => Ok(()),
             Err(e) => Err(format!("Failed to write to file '{}': {}", path_str, e)),
@@ -77,13 +97,8 @@ impl Interpreter {
     }
 
     pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> std::result::Result<(), String> {
-        self.pool.import_file(path)?;
-        let mut checker = TypeChecker::new(&self.pool);
-        if let Err(err) = checker.check_program() {
-            return Err(format!("Type check error: {}", err));
-        }
-        self.compiled_functions.compile(&self.pool);
-        Ok(())
+        // The file may redefine memoised functions
+        self.update_program(|pool| pool.import_file(path), HashMap::new(), true)
     }
 
     pub fn save_file<P: AsRef<Path>>(&self, path: P) -> std::result::Result<(), String> {
@@ -98,63 +113,185 @@ impl Interpreter {
         }
     }
 
-    pub fn eval_expression(&mut self, expr: &str) -> std::result::Result<Value, String> {
-        if expr.trim().starts_with("fn ") {
-            match parse_program(expr, &mut self.pool) {
-                Ok(_) => {
-                    let mut checker = TypeChecker::new(&self.pool);
-                    if let Err(err) = checker.check_program() {
-                        return Err(format!("Type check error: {}", err));
-                    }
-
-                    self.compiled_functions = CompiledFunctions::new();
-                    self.compiled_functions.compile(&self.pool);
-
-                    Ok(Value::Int(0)) // Return a dummy value
-                }
-                Err(e) => Err(format!("Parse error: {}", e)),
+    /// Whether the input is a top-level definition rather than an expression
+    fn is_definition(input: &str) -> bool {
+        let mut input = input.trim_start();
+        while let Some(rest) = input.strip_prefix("///") {
+            input = rest
+                .split_once('\n')
+                .map_or("", |(_, rest)| rest)
+                .trim_start();
+        }
+        // `#[..]` is an array literal unless a definition follows
+        while let Some(rest) = input.strip_prefix("#[") {
+            match rest.find(']') {
+                Some(end) => input = rest[end + 1..].trim_start(),
+                None => return false,
             }
+        }
+        [
+            "fn ", "const ", "effect ", "macro ", "infixl ", "infixr ", "infix ",
+        ]
+        .iter()
+        .any(|keyword| input.starts_with(keyword))
+    }
+
+    pub fn eval_expression(&mut self, expr: &str) -> std::result::Result<Value, String> {
+        if Self::is_definition(expr) {
+            self.update_program(|pool| parse_input(expr, pool), HashMap::new(), true)?;
+            Ok(Value::Int(0)) // Return a dummy value
         } else {
             let synthetic_fn = format!("fn __eval__() {{ {} }}", expr);
+            // The definitions are unchanged, so are the results of the
+            // memoised functions
+            let memo_caches = self.compiled_functions.memo_caches().clone();
+            self.update_program(|pool| parse_input(&synthetic_fn, pool), memo_caches, false)?;
 
-            match parse_program(&synthetic_fn, &mut self.pool) {
-                Ok(_) => {
-                    let mut checker = TypeChecker::new(&self.pool);
-                    if let Err(err) = checker.check_program() {
-                        return Err(format!("Type check error: {}", err));
-                    }
-
-                    self.compiled_functions = CompiledFunctions::new();
-                    self.compiled_functions.compile(&self.pool);
-
-                    let name_idx = self.pool.intern_string("__eval__");
-                    if let Some(&ast_idx) = self.pool.function_defs.get(&name_idx) {
-                        if let Ast::FunctionDef { body_idx, .. } = self.pool[ast_idx] {
-                            match self.compiled_functions.execute(body_idx, &self.pool) {
-                                Some(value) => Ok(value),
-                                None => Err("Failed to execute expression".to_string()),
-                            }
-                        } else {
-                            Err("Internal error: __eval__ is not a function definition".to_string())
-                        }
-                    } else {
-                        Err("Internal error: __eval__ function not found".to_string())
+            let name_idx = self.pool.intern_string("__eval__");
+            if let Some(&ast_idx) = self.pool.function_defs.get(&name_idx) {
+                if let Ast::FunctionDef { body_idx, .. } = self.pool[ast_idx] {
+                    match self.compiled_functions.execute(body_idx, &self.pool) {
+                        Some(value) => Ok(value),
+                        None => Err("Failed to execute expression".to_string()),
                     }
+                } else {
+                    Err("Internal error: __eval__ is not a function definition".to_string())
                 }
-                Err(e) => Err(format!("Parse error: {}", e)),
+            } else {
+                Err("Internal error: __eval__ function not found".to_string())
+            }
+        }
+    }
+
+    /// Add to the program with `update`, then check and compile it. If any
+    /// of this fails the program is left as it was, so that a rejected input
+    /// does not break the ones after it.
+    fn update_program(
+        &mut self,
+        update: impl FnOnce(&mut AstPool) -> std::result::Result<(), String>,
+        memo_caches: HashMap<NameIdx, MemoCache>,
+        show_warnings: bool,
+    ) -> std::result::Result<(), String> {
+        let saved = self.pool.clone();
+        match update(&mut self.pool).and_then(|()| self.compile_program(memo_caches, show_warnings))
+        {
+            Ok(compiled) => {
+                self.compiled_functions = compiled;
+                Ok(())
+            }
+            Err(e) => {
+                self.pool = saved;
+                Err(e)
+            }
+        }
+    }
+
+    fn compile_program(
+        &mut self,
+        memo_caches: HashMap<NameIdx, MemoCache>,
+        show_warnings: bool,
+    ) -> std::result::Result<CompiledFunctions, String> {
+        let mut checker = TypeChecker::new(&self.pool);
+        if let Err(err) = checker.check_program() {
+            return Err(format!("Type check error: {}", err));
+        }
+        if show_warnings {
+            for warning in checker.warnings() {
+                eprintln!("Warning: {}", warning);
             }
         }
+        CompiledFunctions::resolve_comptime(&mut self.pool)
+            .map_err(|e| format!("Compile-time evaluation error: {}", e.describe()))?;
+
+        let mut compiled = CompiledFunctions::new();
+        compiled.keep_memo_caches(memo_caches);
+        compiled
+            .compile(&self.pool)
+            .map_err(|e| format!("Compile error: {}", e.describe()))?;
+        Ok(compiled)
+    }
+
+    /// Call the functions marked `#[test]`, sorted by name. A test fails if
+    /// it returns false or cannot be evaluated.
+    pub fn run_tests(&mut self) -> Vec<(String, std::result::Result<(), String>)> {
+        let mut names: Vec<String> = self
+            .pool
+            .function_defs
+            .keys()
+            .filter(|&&name_idx| self.pool.attribute(name_idx, "test").is_some())
+            .map(|&name_idx| self.pool.get_string(name_idx).to_string())
+            .collect();
+        names.sort();
+
+        names
+            .into_iter()
+            .map(|name| {
+                let result = match self.eval_expression(&format!("{}()", name)) {
+                    Ok(Value::Bool(false)) => Err("returned false".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e),
+                };
+                (name, result)
+            })
+            .collect()
+    }
+
+    /// Number of results cached for each function marked `#[memo]`, sorted
+    /// by name
+    pub fn memo_cache_sizes(&self) -> Vec<(String, usize)> {
+        let mut sizes: Vec<(String, usize)> = self
+            .compiled_functions
+            .memo_caches()
+            .iter()
+            .map(|(&name_idx, cache)| (self.pool.get_string(name_idx).to_string(), cache.len()))
+            .collect();
+        sizes.sort();
+        sizes
+    }
+
+    /// Forget the results of the functions marked `#[memo]`
+    pub fn clear_memo_caches(&mut self) {
+        for cache in self.compiled_functions.memo_caches().values() {
+            cache.clear();
+        }
     }
 
     pub fn pretty_print_function(&self, func_name: &str) -> std::result::Result<String, String> {
+        self.print_function_with(func_name, PrintConfig::default())
+    }
+
+    /// Pretty print a function with its macro calls expanded
+    pub fn pretty_print_expanded(&self, func_name: &str) -> std::result::Result<String, String> {
+        let config = PrintConfig {
+            expand_macros: true,
+            ..PrintConfig::default()
+        };
+        self.print_function_with(func_name, config)
+    }
+
+    /// The doc comment of a function, without the slashes
+    pub fn function_doc(&self, func_name: &str) -> std::result::Result<Option<&str>, String> {
+        match self.pool.get_name_idx_from_func(func_name) {
+            Some(name_idx) if self.pool.function_defs.contains_key(&name_idx) => {
+                Ok(self.pool.docs.get(&name_idx).map(String::as_str))
+            }
+            _ => Err(format!("Function '{}' not found", func_name)),
+        }
+    }
+
+    fn print_function_with(
+        &self,
+        func_name: &str,
+        config: PrintConfig,
+    ) -> std::result::Result<String, String> {
         let name_idx = self
             .pool
             .get_name_idx_from_func(func_name)
             .ok_or("No such function")?;
 
         // Find the function definition
-        if let Some(&ast_idx) = self.pool.function_defs.get(&name_idx) {
-            let printer = PrettyPrinter::new(&self.pool);
+        if let Some(ast_idx) = self.pool.get_global_def(name_idx) {
+            let printer = PrettyPrinter::with_config(&self.pool, config);
             Ok(printer.print_node(ast_idx))
         } else {
             Err(format!("Function '{}' not found", func_name))
@@ -199,6 +336,12 @@ impl Interpreter {
                         "  pretty                        - Pretty print all defined functions"
                     );
                     println!("  pretty       <func>?          - Pretty print <func>");
+                    println!(
+                        "  expand      <func>            - Pretty print <func> with macros expanded"
+                    );
+                    println!("  doc         <func>            - Show the doc comment of <func>");
+                    println!("  test                          - Run the functions marked #[test]");
+                    println!("  memo        clear?            - Show or clear the #[memo] caches");
                     println!("  ast                           - Display the current AST");
                     println!("  reset                         - Reset the interpreter state");
                     println!("  <expr>                        - Evaluate an expression");
@@ -221,12 +364,49 @@ impl Interpreter {
                     println!("{}", printer);
                 }
                 "funcs" => {
+                    let mut checker = TypeChecker::new(&self.pool);
+                    let checked = checker.check_program().is_ok();
+
                     println!("Defined functions:");
                     for (&name_idx, _) in &self.pool.function_defs {
-                        println!("  {}", self.pool.get_string(name_idx));
+                        let impure = checked && !checker.is_pure(name_idx);
+                        let effects = checker.effect_row(name_idx);
+                        println!(
+                            "  {}{}{}",
+                            self.pool.get_string(name_idx),
+                            if impure { " (impure)" } else { "" },
+                            if effects.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" performs {}", effects.join(", "))
+                            }
+                        );
                     }
                 }
 
+                "test" => {
+                    let results = self.run_tests();
+                    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+                    for (name, result) in &results {
+                        match result {
+                            Ok(()) => println!("  {} ... ok", name),
+                            Err(e) => println!("  {} ... FAILED: {}", name, e),
+                        }
+                    }
+                    println!("{} passed, {} failed", results.len() - failed, failed);
+                }
+
+                "memo" => {
+                    for (name, size) in self.memo_cache_sizes() {
+                        println!("  {}: {} cached", name, size);
+                    }
+                }
+
+                "memo clear" => {
+                    self.clear_memo_caches();
+                    println!("Memo caches cleared");
+                }
+
                 "ast" => {
                     println!("Current AST:");
                     self.pool.display();
@@ -263,6 +443,21 @@ impl Interpreter {
                         Err(e) => println!("Error: {}", e),
                     }
                 }
+                _ if input.starts_with("doc ") => {
+                    let func_name = input[4..].trim();
+                    match self.function_doc(func_name) {
+                        Ok(Some(doc)) => println!("{}", doc),
+                        Ok(None) => println!("'{}' is not documented", func_name),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                _ if input.starts_with("expand ") => {
+                    let func_name = input[7..].trim();
+                    match self.pretty_print_expanded(func_name) {
+                        Ok(code) => println!("{}", code),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
                 _ if input.starts_with("save ") => {
                     let file_path = input[5..].trim();
                     if file_path.is_empty() {
@@ -339,8 +534,8 @@ impl Interpreter {
                 let arg_values = args.to_vec();
                 let call_func = crate::compiler::function::CompiledFunction::new(
                     move |mem: &mut Vec<Value>| {
-                        // Push arguments to memory
-                        for arg in &arg_values {
+                        // Push arguments to memory, the first one ends up on top
+                        for arg in arg_values.iter().rev() {
                             mem.push(arg.clone());
                         }
                         Ok(())
@@ -391,6 +586,13 @@ impl Interpreter {
 }
 
 /// Run the interpreter with command line arguments
+/// Parse an input of the REPL into the program
+fn parse_input(input: &str, pool: &mut AstPool) -> std::result::Result<(), String> {
+    parse_program(input, pool)
+        .map(|_| ())
+        .map_err(|e| format!("Parse error: {}", e))
+}
+
 pub fn run_interpreter() -> std::result::Result<(), String> {
     let mut interpreter = Interpreter::new();
 
@@ -432,3 +634,1713 @@ pub fn run_interpreter() -> std::result::Result<(), String> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Interpreter;
+    use crate::ast::pretty_printer::PrettyPrinter;
+    use crate::ast::{Ast, AttributeArg};
+    use crate::checker::type_check::TypeChecker;
+    use crate::value::{Array, Map, Value};
+    use std::rc::Rc;
+
+    fn eval_all(interpreter: &mut Interpreter, inputs: &[&str]) -> Value {
+        let mut result = Value::Unit;
+        for input in inputs {
+            result = interpreter
+                .eval_expression(input)
+                .unwrap_or_else(|e| panic!("Failed to evaluate '{}': {}", input, e));
+        }
+        result
+    }
+
+    /// Evaluates each input on its own and compares the result with the
+    /// expected value
+    fn assert_evals(interpreter: &mut Interpreter, cases: &[(&str, Value)]) {
+        for (input, expected) in cases {
+            let result = eval_all(interpreter, &[input]);
+            assert!(
+                matches!(result.equals(expected), Ok(true)),
+                "{} gave {:?} instead of {:?}",
+                input,
+                result,
+                expected
+            );
+        }
+    }
+
+    /// Checks that each input is rejected with an error mentioning the
+    /// expected text
+    fn assert_rejects(interpreter: &mut Interpreter, cases: &[(&str, &str)]) {
+        for (input, expected) in cases {
+            let result = interpreter.eval_expression(input);
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains(expected)),
+                "{} gave {:?}",
+                input,
+                result
+            );
+        }
+    }
+
+    /// Checks that evaluating each input raises an error whose message
+    /// mentions the expected text
+    fn assert_raises(interpreter: &mut Interpreter, cases: &[(&str, &str)]) {
+        for (input, expected) in cases {
+            let caught = format!("try {{ {} }} catch e {{ message(e) }}", input);
+            let result = interpreter.eval_expression(&caught);
+            assert!(
+                matches!(&result, Ok(Value::Str(message)) if message.contains(expected)),
+                "{} gave {:?}",
+                input,
+                result
+            );
+        }
+    }
+
+    fn ints(items: &[i64]) -> Vec<Value> {
+        items.iter().map(|&i| Value::Int(i)).collect()
+    }
+
+    fn list(items: Vec<Value>) -> Value {
+        Value::List(items.into())
+    }
+
+    fn array(items: Vec<Value>) -> Value {
+        Value::Array(Array::new(items))
+    }
+
+    fn some(value: Value) -> Value {
+        Value::Option(Some(Rc::new(value)))
+    }
+
+    fn map(entries: &[(i64, i64)]) -> Value {
+        let map = entries.iter().fold(Map::default(), |map, &(key, value)| {
+            map.insert(Value::Int(key), Value::Int(value)).unwrap()
+        });
+        Value::Map(map)
+    }
+
+    #[test]
+    fn test_partial_application() {
+        let mut interpreter = Interpreter::new();
+        eval_all(&mut interpreter, &["fn add3(a, b, c) { a + b * c }"]);
+
+        for expr in [
+            "add3(1, 2, 3)",
+            "add3(1)(2)(3)",
+            "add3(1, 2)(3)",
+            "add3(1)(2, 3)",
+        ] {
+            let result = eval_all(&mut interpreter, &[expr]);
+            assert!(
+                matches!(result, Value::Int(7)),
+                "{} gave {:?}",
+                expr,
+                result
+            );
+        }
+
+        let result = eval_all(&mut interpreter, &["add3(1)"]);
+        assert!(matches!(result, Value::Fun(ref f) if f.param_count == 2));
+
+        // A lambda capturing a variable used only in its callee
+        let result = eval_all(
+            &mut interpreter,
+            &["fn adder(q) { lambda z { add3(q)(z, 1) } }", "adder(1)(6)"],
+        );
+        assert!(matches!(result, Value::Int(7)), "got {:?}", result);
+    }
+
+    #[test]
+    fn test_rejected_inputs_are_rolled_back() {
+        let mut interpreter = Interpreter::new();
+        eval_all(&mut interpreter, &["fn inc(x) { x + 1 }"]);
+
+        for input in [
+            "fn bad(x) { add(1, 2, 3) }",
+            "fn f(x) { g(x) }",
+            "const X = Y + 1 const Y = X",
+            "inc(1, 2, 3)",
+            "fn inc(x) { x + ",
+        ] {
+            let result = interpreter.eval_expression(input);
+            assert!(result.is_err(), "{} gave {:?}", input, result);
+
+            let result = eval_all(&mut interpreter, &["inc(1 + 1)"]);
+            assert!(
+                matches!(result, Value::Int(3)),
+                "after {}: {:?}",
+                input,
+                result
+            );
+        }
+        let pool = &interpreter.pool;
+        for name in ["bad", "f", "X", "Y"] {
+            let def = pool
+                .get_name_idx_from_func(name)
+                .and_then(|name_idx| pool.get_global_def(name_idx));
+            assert!(def.is_none(), "{} was kept", name);
+        }
+    }
+
+    #[test]
+    fn test_over_application() {
+        let mut interpreter = Interpreter::new();
+        let result = eval_all(
+            &mut interpreter,
+            &[
+                "fn curry(a, b) { lambda c d { lambda e { a + b * c + d * e } } }",
+                "curry(1, 2, 3, 4, 5)",
+            ],
+        );
+        assert!(matches!(result, Value::Int(27)), "got {:?}", result);
+
+        let result = eval_all(
+            &mut interpreter,
+            &["fn apply(f, x) { f(x) }", "apply(add(1), 4)"],
+        );
+        assert!(matches!(result, Value::Int(5)), "got {:?}", result);
+
+        // Only results that may be functions take more arguments
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn add3(a, b, c) { a + b * c }",
+                "fn pick(fs, i) { fs[i] }",
+                "fn countdown(n) | n < 1 = 0 | _ = countdown(n + -1)",
+            ],
+        );
+        assert_evals(
+            &mut interpreter,
+            &[("pick(#[(+ 1), (* 2)], 1, 5)", Value::Int(10))],
+        );
+        assert_rejects(
+            &mut interpreter,
+            &[
+                ("add3(1, 2, 3, 4)", "'add3' expected 3 arguments but got 4"),
+                (
+                    "fn bad(x) { add3(x, x, x, x) }",
+                    "'add3' expected 3 arguments",
+                ),
+                (
+                    "countdown(3, 1)",
+                    "'countdown' expected 1 arguments but got 2",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_operator_sections() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn apply(f, x) { f(x) }",
+                "fn apply2(f, x, y) { f(x, y) }",
+                "fn scale(k, x) { apply((* k + 1), x) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("apply2((+), 3, 4)", Value::Int(7)),
+                ("apply2(multiply, 3, 4)", Value::Int(12)),
+                ("apply((* 3), 5)", Value::Int(15)),
+                ("apply((2 + 3 *), 5)", Value::Int(25)),
+                ("scale(2, 5)", Value::Int(15)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_operator_sections_pretty_print() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn apply2(f, x, y) { f(x, y) }",
+                "fn sections(k) { apply2((+), (* k), (2 *)) }",
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("sections").unwrap();
+        assert!(code.contains("apply2((+), (* k), (2 *))"), "got {}", code);
+    }
+
+    #[test]
+    fn test_calls_of_expressions() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn inc(x) { x + 1 }",
+                "fn dbl(x) { x * 2 }",
+                "fn curried(a) { add(a)(1) }",
+                "fn section(a) { (* 2)(a) }",
+                "fn composed(a) { (inc >> dbl)(a) }",
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("composed").unwrap();
+        assert!(code.contains("(inc >> dbl)(a)"), "got {}", code);
+
+        // The saved program reads back with the same meaning
+        let saved = PrettyPrinter::new(&interpreter.pool).print_all_functions();
+        let mut reloaded = Interpreter::new();
+        eval_all(&mut reloaded, &[&saved]);
+        assert_evals(
+            &mut reloaded,
+            &[
+                ("curried(4)", Value::Int(5)),
+                ("section(5)", Value::Int(10)),
+                ("composed(3)", Value::Int(8)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_user_defined_operators() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn vadd(a, b) { a * 10 + b }",
+                "fn apply(f, x) { f(x) }",
+                "infixl 5 <+> = vadd",
+                "infixr 5 +> = vadd",
+                "infixl 8 <**> = multiply",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("1 <+> 2 <+> 3", Value::Int(123)),
+                ("1 +> 2 +> 3", Value::Int(33)),
+                ("1 + 2 <+> 3", Value::Int(33)),
+                ("2 <**> 3 + 1", Value::Int(7)),
+                ("1 + 2 <**> 3", Value::Int(7)),
+                ("apply((<+> 4), 3)", Value::Int(34)),
+            ],
+        );
+
+        assert_rejects(
+            &mut interpreter,
+            &[
+                ("1 <+> 2 +> 3", "Operator '+>' cannot be chained"),
+                ("1 <-> 2", "Unknown operator '<->'"),
+                ("infixl 1 + = vadd", "Cannot redefine built-in operator '+'"),
+                (
+                    "infixl 6 <-> = nosuch",
+                    "Operator '<->' is declared for the unknown function 'nosuch'",
+                ),
+                (
+                    "infixl 5 <+> = multiply",
+                    "Operator '<+>' is already declared as `infixl 5 <+> = vadd`",
+                ),
+            ],
+        );
+
+        // Functions can follow the operators declared for them and an
+        // operator can be declared again as it was
+        eval_all(
+            &mut interpreter,
+            &[
+                "infixl 4 <-> = vsub fn vsub(a, b) { a + b * -1 }",
+                "infixl 5 <+> = vadd",
+            ],
+        );
+        assert_evals(
+            &mut interpreter,
+            &[("5 <-> 2", Value::Int(3)), ("1 <+> 2", Value::Int(12))],
+        );
+    }
+
+    #[test]
+    fn test_user_defined_operators_pretty_print() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn vadd(a, b) { a * 10 + b }",
+                "infixr 5 <+> = vadd",
+                "fn ops(a, b, c) { (a <+> b) <+> c * (a + b) }",
+                "fn right(a, b, c) { a <+> b <+> (<+> c) }",
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("ops").unwrap();
+        assert!(code.contains("(a <+> b) <+> c * (a + b)"), "got {}", code);
+        let code = interpreter.pretty_print_function("right").unwrap();
+        assert!(code.contains("a <+> b <+> (<+> c)"), "got {}", code);
+
+        let printer = PrettyPrinter::new(&interpreter.pool);
+        let source = printer.print_all_functions();
+        assert!(
+            source.starts_with("infixr 5 <+> = vadd\n"),
+            "got {}",
+            source
+        );
+
+        let mut reloaded = Interpreter::new();
+        eval_all(&mut reloaded, &[source.as_str()]);
+        let result = eval_all(&mut reloaded, &["ops(1, 2, 3)"]);
+        assert!(matches!(result, Value::Int(129)), "got {:?}", result);
+    }
+
+    #[test]
+    fn test_pipeline_and_composition() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn inc(x) { x + 1 }",
+                "fn double(x) { x * 2 }",
+                "fn apply(f, x) { f(x) }",
+                "fn pipeline(x) { x |> inc >> double |> add(1) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("3 |> inc |> double", Value::Int(8)),
+                ("3 |> double |> inc", Value::Int(7)),
+                ("1 + 2 |> double", Value::Int(6)),
+                ("apply(inc >> double, 3)", Value::Int(8)),
+                ("apply(double >> inc >> inc, 3)", Value::Int(8)),
+                ("3 |> (|> inc)", Value::Int(4)),
+                ("pipeline(3)", Value::Int(9)),
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("pipeline").unwrap();
+        assert!(code.contains("x |> inc >> double |> (1 +)"), "got {}", code);
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "const TWICE = LIMIT * 2 const LIMIT = 100 * 3",
+                "fn scaled(x) { x * TWICE }",
+                "const SCALED = scaled(2)",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("LIMIT", Value::Int(300)),
+                ("TWICE", Value::Int(600)),
+                ("SCALED", Value::Int(1200)),
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("TWICE").unwrap();
+        assert_eq!(code, "const TWICE = LIMIT * 2");
+
+        assert_rejects(
+            &mut interpreter,
+            &[("const X = Y + 1 const Y = X", "depends on itself")],
+        );
+
+        // Constants and functions share one namespace
+        assert_rejects(
+            &mut interpreter,
+            &[
+                (
+                    "const TWICE = 2 fn TWICE(x) { x * 2 }",
+                    "already defined as a constant",
+                ),
+                (
+                    "fn inc(x) { x + 1 } const inc = 1",
+                    "already defined as a function",
+                ),
+            ],
+        );
+
+        // Primitives keep their meaning
+        assert_rejects(
+            &mut interpreter,
+            &[
+                ("fn map(x) { x }", "'map' is a built-in function"),
+                ("const len = 1", "'len' is a built-in function"),
+                ("fn add(a, b) { a }", "'add' is a built-in function"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_constant_functions() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn apply(f, x) { f(x) }",
+                "const INC = lambda x { x + 1 }",
+                "const DOUBLED = collect(map(0..4, (* 2)))",
+                "fn early(u) { comptime { apply(lambda x { x * 3 }, 2) } }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("apply(INC, 41)", Value::Int(42)),
+                ("DOUBLED", list(ints(&[0, 2, 4, 6]))),
+                ("early(0)", Value::Int(6)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_default_and_named_arguments() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn uses_later() { connect(1, retries: 5) }
+                 fn connect(host, port = 80, retries = 3) { host * 10000 + port * 10 + retries }",
+                "fn partial(a, b, c = 1) { a + b + c }",
+                "fn apply(f, x) { f(x) }",
+                "fn scaled(x, by = lambda y { y * 2 }) { by(x) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("connect(1)", Value::Int(10803)),
+                ("connect(1, 2)", Value::Int(10023)),
+                ("connect(1, retries: 5)", Value::Int(10805)),
+                ("connect(port: 7, host: 2)", Value::Int(20073)),
+                ("uses_later()", Value::Int(10805)),
+                ("partial(1, 2)", Value::Int(4)),
+                ("partial(1, 2, 3)", Value::Int(6)),
+                ("partial(1)(2)", Value::Int(4)),
+                ("apply(connect, 1)", Value::Int(10803)),
+                ("apply(partial(1), 2)", Value::Int(4)),
+                ("scaled(4)", Value::Int(8)),
+                ("scaled(4, by: lambda y { y + 1 })", Value::Int(5)),
+            ],
+        );
+
+        assert_rejects(
+            &mut interpreter,
+            &[
+                ("connect(1, timeout: 5)", "has no parameter named 'timeout'"),
+                (
+                    "connect(1, host: 2)",
+                    "Argument 'host' of function 'connect' is given more than once",
+                ),
+                (
+                    "connect(1, port: 2, port: 3)",
+                    "Argument 'port' of function 'connect' is given more than once",
+                ),
+                (
+                    "connect(retries: 5)",
+                    "Argument 'host' of function 'connect' is missing and has no default",
+                ),
+                (
+                    "fn f(a, b = a + 1) { a + b }",
+                    "Default value of 'b' cannot use parameter 'a'",
+                ),
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("connect").unwrap();
+        assert!(
+            code.starts_with("fn connect(host, port = 80, retries = 3)"),
+            "got {}",
+            code
+        );
+        let code = interpreter.pretty_print_function("uses_later").unwrap();
+        assert!(code.contains("connect(1, retries: 5)"), "got {}", code);
+
+        // The saved program reads back with the same meaning
+        let saved = PrettyPrinter::new(&interpreter.pool).print_all_functions();
+        let mut reloaded = Interpreter::new();
+        eval_all(&mut reloaded, &[&saved]);
+        assert_evals(
+            &mut reloaded,
+            &[
+                ("uses_later()", Value::Int(10805)),
+                ("scaled(4)", Value::Int(8)),
+            ],
+        );
+
+        // Parameters keep their names even if no call names them yet
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &["fn scale(value, by = 2) { value * by }"],
+        );
+        let saved = PrettyPrinter::new(&interpreter.pool).print_all_functions();
+        assert!(saved.contains("fn scale(value, by = 2)"), "got {}", saved);
+        let mut reloaded = Interpreter::new();
+        eval_all(&mut reloaded, &[&saved]);
+        assert_evals(&mut reloaded, &[("scale(by: 3, value: 5)", Value::Int(15))]);
+    }
+
+    #[test]
+    fn test_variadic_functions() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn sum(...xs) { fold(xs, 0, (+)) }",
+                "fn sum_list(xs) { fold(xs, 0, (+)) }",
+                "fn scaled_sum(factor, ...xs) { factor * sum_list(xs) }",
+                "fn gather(...xs) { xs }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("sum()", Value::Int(0)),
+                ("sum(4)", Value::Int(4)),
+                ("sum(1, 2, 3)", Value::Int(6)),
+                ("scaled_sum(10, 1, 2)", Value::Int(30)),
+                ("scaled_sum(10)", Value::Int(0)),
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[("gather(1, 2, 3)", list(ints(&[1, 2, 3])))],
+        );
+
+        let code = interpreter.pretty_print_function("scaled_sum").unwrap();
+        assert!(
+            code.starts_with("fn scaled_sum(factor, ...xs)"),
+            "got {}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_where_clauses() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn area(r) { pi * sq(r) } where sq(x) { x * x }, pi = 3",
+                "fn offset(a, b) { scale(a) + shift } where scale(x) { x * b }, shift = b + 1",
+                "fn apply(f, x) { f(x) }",
+                "fn adders(n) { apply(lambda x { x + base }, n) } where base = n * 10",
+                "fn chained(r) { b + twice(c) } where a = r * 2, b = a + 1, twice(x) { x * a }, c = twice(b)",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("area(2)", Value::Int(12)),
+                ("offset(3, 4)", Value::Int(17)),
+                ("adders(2)", Value::Int(22)),
+                // a = 6, b = 7, c = 42
+                ("chained(3)", Value::Int(259)),
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("area").unwrap();
+        assert!(
+            code.contains("pi * sq(r)\n} where sq(l2p0) { l2p0 * l2p0 }, pi = 3"),
+            "got {}",
+            code
+        );
+
+        // Printed code parses back to the same definition
+        let mut reloaded = Interpreter::new();
+        eval_all(&mut reloaded, &[code.as_str()]);
+        let result = eval_all(&mut reloaded, &["area(2)"]);
+        assert!(matches!(result, Value::Int(12)), "got {:?}", result);
+
+        let code = interpreter.pretty_print_function("chained").unwrap();
+        assert!(
+            code.contains("where a = r * 2, b = a + 1, twice(l4p0) { l4p0 * a }, c = twice(b)"),
+            "got {}",
+            code
+        );
+        eval_all(&mut reloaded, &[code.as_str()]);
+        let result = eval_all(&mut reloaded, &["chained(3)"]);
+        assert!(matches!(result, Value::Int(259)), "got {:?}", result);
+
+        assert_rejects(
+            &mut interpreter,
+            &[
+                // Bindings cannot use the ones after them
+                ("fn f(x) { a } where a = b, b = 1", "'b' not found"),
+                (
+                    "fn f(x) { a } where a = 1, a = 2",
+                    "Duplicate local definition 'a' in where clause",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_guarded_clauses() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn sign(x) | x < 0 = -1 | x == 0 = 0 | _ = 1",
+                "fn fact(n) | n < 1 = 1 | _ = n * fact(n + -1)",
+                "fn positive(x) | x > 0 = x",
+                "fn clamp(x) | x > limit = limit | _ = x where limit = 10",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("sign(-5)", Value::Int(-1)),
+                ("sign(0)", Value::Int(0)),
+                ("sign(7)", Value::Int(1)),
+                ("fact(5)", Value::Int(120)),
+                ("positive(3)", Value::Int(3)),
+                ("clamp(4)", Value::Int(4)),
+                ("clamp(40)", Value::Int(10)),
+            ],
+        );
+
+        assert_raises(
+            &mut interpreter,
+            &[(
+                "positive(-3)",
+                "no clause of 'positive' matched its arguments",
+            )],
+        );
+
+        // Only `positive` lacks a catch-all clause
+        let mut checker = TypeChecker::new(&interpreter.pool);
+        checker.check_program().unwrap();
+        assert_eq!(checker.warnings().len(), 1);
+        assert_rejects(
+            &mut interpreter,
+            &[(
+                "fn bad(x) | _ = 1 | x == 0 = 0",
+                "The catch-all clause '_' must be the last clause",
+            )],
+        );
+
+        let code = interpreter.pretty_print_function("sign").unwrap();
+        assert_eq!(
+            code,
+            "fn sign(x)\n\t| x < 0 = -1\n\t| x == 0 = 0\n\t| _ = 1"
+        );
+        let code = interpreter.pretty_print_function("clamp").unwrap();
+        assert!(code.ends_with("\t| _ = x where limit = 10"), "got {}", code);
+    }
+
+    #[test]
+    fn test_option_result_and_try() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn checked(x) | x < 0 = err(x) | _ = ok(x)",
+                "fn half(x) | x == 0 = none | _ = some(x * 5)",
+                "fn total(a, b) { ok(checked(a)? + checked(b)?) }",
+                "fn chain(x) { some(half(half(x)?)? + 1) }",
+                "fn apply(f, x) { f(x) }",
+                "fn inner_return(x) { some(apply(lambda y { half(y)? }, x) + 1) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("total(1, 2)", Value::Result(Ok(Rc::new(Value::Int(3))))),
+                ("total(1, -2)", Value::Result(Err(Rc::new(Value::Int(-2))))),
+                ("total(-1, -2)", Value::Result(Err(Rc::new(Value::Int(-1))))),
+                ("chain(2)", some(Value::Int(51))),
+                ("chain(0)", Value::Option(None)),
+                ("inner_return(2)", some(Value::Int(11))),
+            ],
+        );
+
+        // `?` on a failure inside a lambda returns from the lambda only
+        assert_raises(
+            &mut interpreter,
+            &[("inner_return(0)", "Wrong Argument type for `add`")],
+        );
+
+        let code = interpreter.pretty_print_function("total").unwrap();
+        assert!(
+            code.contains("ok(checked(a)? + checked(b)?)"),
+            "got {}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_try_catch_and_raise() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn deep(n) { 1 + raise(n * 10) }",
+                "fn recover(x) { 3 + try { deep(x) + 5 } catch e { x * 2 } }",
+                "fn caught(x) { try { deep(x) } catch e { payload(e) } }",
+                "fn early(x) { try { some(none? + 1) } catch e { some(x) } }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("recover(4)", Value::Int(11)),
+                ("add(1, try { raise(2) } catch e { 10 })", Value::Int(11)),
+                ("try { 7 } catch e { 0 }", Value::Int(7)),
+                ("caught(4)", some(Value::Int(40))),
+                (
+                    "try { fold(3, 0, (+)) } catch e { payload(e) }",
+                    Value::Option(None),
+                ),
+                ("early(1)", Value::Option(None)),
+            ],
+        );
+
+        assert_raises(&mut interpreter, &[("deep(1)", "raised 10 : Int")]);
+
+        let code = interpreter.pretty_print_function("recover").unwrap();
+        assert!(
+            code.contains("3 + try { deep(x) + 5 } catch l2p0 { x * 2 }"),
+            "got {}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_reference_cells() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn bump(r) { set(r, get(r) + 1) }",
+                "fn count(r, n) | n == 0 = get(r) | _ = count_again(r, n, bump(r))
+                 fn count_again(r, n, done) { count(r, n + -1) }",
+                "fn count_to(n) { count(ref(0), n) }",
+                "fn same(r) { r == r }",
+                "fn first(x, y) { x }",
+                "fn swap(r) { first(get(r), set(r, 41)) + get(r) }",
+                "fn double(x) { x * 2 }",
+                "fn tie(r) { first(r, set(r, r)) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("count_to(5)", Value::Int(5)),
+                ("get(ref(1))", Value::Int(1)),
+                ("same(ref(1))", Value::Bool(true)),
+                ("ref(1) == ref(1)", Value::Bool(false)),
+                ("swap(ref(1))", Value::Int(42)),
+            ],
+        );
+
+        // A reference holding itself prints without recursing
+        let result = eval_all(&mut interpreter, &["tie(ref(0))"]);
+        assert_eq!(format!("{:?}", result), "ref(ref(..) : Ref) : Ref");
+        assert_raises(
+            &mut interpreter,
+            &[("raise(tie(ref(0)))", "raised ref(ref(..) : Ref) : Ref")],
+        );
+
+        let mut checker = TypeChecker::new(&interpreter.pool);
+        checker.check_program().unwrap();
+        for (name, pure) in [
+            ("bump", false),
+            ("count_to", false),
+            ("count_again", false),
+            ("swap", false),
+            ("first", true),
+            ("double", true),
+        ] {
+            let name_idx = interpreter.pool.get_name_idx_from_func(name).unwrap();
+            assert_eq!(checker.is_pure(name_idx), pure, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_arrays() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn squares(a) { #[a[0] * a[0], a[1] * a[1]] }",
+                "fn last(a) { a[len(a) + -1] }",
+                "fn grow(a) { len(push(a, 4)) + len(a) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("#[1, 2, 3]", array(ints(&[1, 2, 3]))),
+                ("#[]", array(vec![])),
+                ("squares(#[2, 3])", array(ints(&[4, 9]))),
+                ("last(#[1, 2, 3])", Value::Int(3)),
+                ("#[#[1], #[2, 5]][1][1]", Value::Int(5)),
+                ("slice(#[1, 2, 3, 4], 1, 3)", array(ints(&[2, 3]))),
+                ("slice(#[1, 2, 3], 1, 3)[0]", Value::Int(2)),
+                ("push(slice(#[1, 2, 3], 0, 1), 9)", array(ints(&[1, 9]))),
+                ("grow(#[1, 2, 3])", Value::Int(7)),
+                ("len(#[some(1)])", Value::Int(1)),
+                ("fold(#[1, 2, 3], 0, (+))", Value::Int(6)),
+                ("fold(slice(#[1, 2, 3], 1, 3), 0, (+))", Value::Int(5)),
+                ("#[1, 2] == #[1, 2]", Value::Bool(true)),
+                ("#[1, 2] == slice(#[0, 1, 2], 1, 3)", Value::Bool(true)),
+                ("#[1, 2] == #[1, 2, 3]", Value::Bool(false)),
+                ("#[some(1)] == #[some(2)]", Value::Bool(false)),
+                ("some(#[1]) == some(#[1])", Value::Bool(true)),
+                ("some(1) == none", Value::Bool(false)),
+                ("ok(1) == err(1)", Value::Bool(false)),
+            ],
+        );
+
+        assert_raises(
+            &mut interpreter,
+            &[
+                (
+                    "#[1, 2, 3][3]",
+                    "index 3 out of bounds for array of length 3",
+                ),
+                (
+                    "#[1, 2, 3][-1]",
+                    "index -1 out of bounds for array of length 3",
+                ),
+                (
+                    "slice(#[1, 2], 1, 3)",
+                    "slice 1..3 out of bounds for array of length 2",
+                ),
+                ("#[(+)] == #[(+)]", "Cannot compare"),
+                ("#[1] == collect(#[1])", "Cannot compare"),
+                (
+                    "fold(1, 0, (+))",
+                    "`fold` expects a list, an array or a sequence",
+                ),
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("squares").unwrap();
+        assert!(code.contains("#[a[0] * a[0], a[1] * a[1]]"), "got {}", code);
+        let code = interpreter.pretty_print_function("last").unwrap();
+        assert!(code.contains("a[len(a) + -1]"), "got {}", code);
+    }
+
+    #[test]
+    fn test_maps() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "const SQUARES = #{1: 1, 2: 4, 3: 9}",
+                "fn square_of(k) { get(SQUARES, k) }",
+                "fn persistent(m) { len(insert(m, 4, 16)) + len(remove(m, 1)) + len(m) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("#{}", map(&[])),
+                ("#{2: 20, 1: 10, 2: 21}", map(&[(1, 10), (2, 21)])),
+                ("square_of(3)", some(Value::Int(9))),
+                ("square_of(5)", Value::Option(None)),
+                ("persistent(SQUARES)", Value::Int(9)),
+                ("keys(remove(SQUARES, 2))", list(ints(&[1, 3]))),
+                ("values(insert(SQUARES, 0, 7))", list(ints(&[7, 1, 4, 9]))),
+                ("contains(SQUARES, 2)", Value::Bool(true)),
+                ("contains(remove(SQUARES, 2), 2)", Value::Bool(false)),
+                ("get(#{#[1, 2]: 3}, #[1, 2])", some(Value::Int(3))),
+                ("get(get(ref(SQUARES)), 2)", some(Value::Int(4))),
+                (
+                    "collect(map(1..3, get(SQUARES)))",
+                    list(vec![some(Value::Int(1)), some(Value::Int(4))]),
+                ),
+                ("SQUARES == #{3: 9, 2: 4, 1: 1}", Value::Bool(true)),
+                ("SQUARES == insert(SQUARES, 3, 8)", Value::Bool(false)),
+                ("SQUARES == remove(SQUARES, 3)", Value::Bool(false)),
+                ("#{1: #[2]} == #{1: #[2]}", Value::Bool(true)),
+            ],
+        );
+
+        assert_raises(
+            &mut interpreter,
+            &[
+                ("insert(SQUARES, (+), 0)", "cannot hash a function"),
+                ("get(1, 2)", "`get` expects a reference or a map"),
+            ],
+        );
+        let mut checker = TypeChecker::new(&interpreter.pool);
+        checker.check_program().unwrap();
+        let name_idx = interpreter
+            .pool
+            .get_name_idx_from_func("square_of")
+            .unwrap();
+        assert!(checker.is_pure(name_idx));
+
+        let code = interpreter.pretty_print_function("SQUARES").unwrap();
+        assert!(code.contains("#{1: 1, 2: 4, 3: 9}"), "got {}", code);
+    }
+
+    #[test]
+    fn test_ranges_and_lazy_sequences() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn upto(n) { collect(1..=n) }",
+                "fn big_squares(n) { collect(take(filter(map(0..1000000000, lambda x { x * x }), lambda x { x > n }), 3)) }",
+                "fn counted(r) { collect(map(zip(0..10, r), lambda p { p[0] + p[1] })) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("collect(1..4)", list(ints(&[1, 2, 3]))),
+                ("upto(3)", list(ints(&[1, 2, 3]))),
+                ("collect(3..3)", list(vec![])),
+                ("collect(1..1 + 2)", list(ints(&[1, 2]))),
+                ("big_squares(50)", list(ints(&[64, 81, 100]))),
+                ("collect(drop(take(0..100, 5), 3))", list(ints(&[3, 4]))),
+                ("counted(#[10, 20])", list(ints(&[10, 21]))),
+                ("collect(zip(#[1], 5..9))", list(vec![array(ints(&[1, 5]))])),
+                ("fold(0..5, 0, (+))", Value::Int(10)),
+                ("fold(map(1..=3, (* 2)), 1, (*))", Value::Int(48)),
+            ],
+        );
+        let result = eval_all(&mut interpreter, &["0..10"]);
+        assert!(matches!(result, Value::Seq(_)), "got {:?}", result);
+
+        // Nothing is produced before it is asked for
+        assert_evals(
+            &mut interpreter,
+            &[(
+                "len(collect(take(map(1..10, lambda x { raise(x) }), 0)))",
+                Value::Int(0),
+            )],
+        );
+
+        let code = interpreter.pretty_print_function("upto").unwrap();
+        assert!(code.contains("collect(1 ..= n)"), "got {}", code);
+    }
+
+    #[test]
+    fn test_lazy_and_force() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn nats(n) { #[n, lazy nats(n + 1)] }
+                 fn nth(s, i) | i == 0 = s[0] | _ = nth(force(s[1]), i + -1)",
+                "fn first(x, y) { x }",
+                "fn tick(r) { first(get(r) + 1, set(r, get(r) + 1)) }",
+                "fn twice(r) { force(t) + force(t) + get(r) } where t = lazy tick(r)",
+                "fn pick(flag, fallback = lazy raise(1)) | flag = 1 | _ = force(fallback)",
+                "fn delayed(x) { lazy (x * 2) }",
+                "fn scaled(x) { lazy x * 1000 }",
+                "fn both(a, b) { force(a) + force(b) }",
+                "infixl 6 <&> = both",
+                "fn lazy_operands(x) { (lazy x) <&> (lazy x * 2) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("nth(nats(0), 5)", Value::Int(5)),
+                ("twice(ref(0))", Value::Int(3)),
+                ("pick(1 == 1)", Value::Int(1)),
+                ("pick(1 == 2, lazy 4)", Value::Int(4)),
+                ("force(delayed(21))", Value::Int(42)),
+                ("force(7)", Value::Int(7)),
+                // `lazy` takes the whole expression after it
+                ("force(scaled(2))", Value::Int(2000)),
+                ("lazy_operands(5)", Value::Int(15)),
+            ],
+        );
+
+        // Nothing is computed before it is forced
+        let result = eval_all(&mut interpreter, &["delayed(1)"]);
+        assert!(
+            matches!(&result, Value::Thunk(thunk) if thunk.value().is_none()),
+            "got {:?}",
+            result
+        );
+
+        let code = interpreter.pretty_print_function("delayed").unwrap();
+        assert!(code.contains("lazy x * 2"), "got {}", code);
+        let code = interpreter.pretty_print_function("lazy_operands").unwrap();
+        assert!(code.contains("(lazy x) <&> (lazy x * 2)"), "got {}", code);
+
+        // The saved program reads back with the same meaning
+        let saved = PrettyPrinter::new(&interpreter.pool).print_all_functions();
+        let mut reloaded = Interpreter::new();
+        eval_all(&mut reloaded, &[&saved]);
+        assert_evals(
+            &mut reloaded,
+            &[
+                ("force(scaled(2))", Value::Int(2000)),
+                ("lazy_operands(5)", Value::Int(15)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_generators() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn countdown(n) | n == 0 = #[] | _ = yield n; countdown(n + -1)",
+                "fn nats(n) { yield n; nats(n + 1) }",
+                "fn running(s, acc) { emit(next(s)?, acc) }
+                 fn emit(step, acc) { yield acc + step[0]; running(step[1], acc + step[0]) }",
+                "fn bump(r) { yield get(r); yield set(r, get(r) + 1) }",
+                "fn once(r) { len(collect(s)) + len(collect(s)) + get(r) } where s = bump(r)",
+                "fn tagged(n) { #[n, yield n * 10] }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("collect(countdown(3))", list(ints(&[3, 2, 1]))),
+                (
+                    "collect(take(map(nats(0), (* 2)), 3))",
+                    list(ints(&[0, 2, 4])),
+                ),
+                ("collect(running(#[1, 2, 3], 0))", list(ints(&[1, 3, 6]))),
+                (
+                    "collect(running(take(nats(1), 4), 0))",
+                    list(ints(&[1, 3, 6, 10])),
+                ),
+                ("collect(yield 1)", list(ints(&[1]))),
+                ("next(countdown(0))", Value::Option(None)),
+                // The rest of a generator runs once however often it is walked
+                ("once(ref(0))", Value::Int(5)),
+                // A nested yield is a sequence value and does not pause `tagged`
+                ("len(tagged(1))", Value::Int(2)),
+                ("collect(tagged(1)[1])", list(ints(&[10]))),
+            ],
+        );
+
+        let result = eval_all(&mut interpreter, &["next(countdown(1))"]);
+        assert!(
+            matches!(&result, Value::Option(Some(step)) if matches!(&**step,
+                Value::Array(step) if matches!(step.as_slice(), [Value::Int(1), Value::Seq(_)]))),
+            "got {:?}",
+            result
+        );
+
+        let code = interpreter.pretty_print_function("nats").unwrap();
+        assert!(code.contains("yield n; nats(n + 1)"), "got {}", code);
+    }
+
+    #[test]
+    fn test_continuations() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn check(k, acc, x) | x < 0 = k(some(x)) | _ = acc",
+                "fn find_negative(xs) { callcc(lambda k { fold(xs, none, lambda acc x { check(k, acc, x) }) }) }",
+                "fn both(a, b) { shift(lambda k { k(a) + k(b) }) }",
+                "fn escaped(x) { callcc(lambda k { k }) }",
+                "fn then(a, b) { b }",
+                "fn add_to(r, x) { then(set(r, get(r) + x), get(r)) }",
+                "fn refs_after(r) { reset(lambda { shift(lambda k { k(1) + k(2) }) + add_to(r, 100) }) }",
+                "fn refs_before(r) { reset(lambda { then(add_to(r, 100), shift(lambda k { k(1) + k(1) })) }) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                (
+                    "find_negative(collect(#[1, -2, 3, -4]))",
+                    some(Value::Int(-2)),
+                ),
+                ("find_negative(collect(#[1, 2]))", Value::Option(None)),
+                (
+                    "reset(lambda { 1 + shift(lambda k { k(k(10)) }) })",
+                    Value::Int(12),
+                ),
+                ("reset(lambda { 1 + shift(lambda k { 5 }) })", Value::Int(5)),
+                (
+                    "100 + reset(lambda { add(5, shift(lambda k { k(1) + k(2) })) })",
+                    Value::Int(113),
+                ),
+                // Every combination of choices is tried
+                (
+                    "reset(lambda { both(1, 2) * both(10, 100) })",
+                    Value::Int(330),
+                ),
+                ("reset(lambda { 7 })", Value::Int(7)),
+                // Each resumption runs what follows the shift once
+                ("refs_after(ref(2))", Value::Int(307)),
+            ],
+        );
+
+        assert_raises(
+            &mut interpreter,
+            &[
+                ("shift(lambda k { 1 })", "shift called outside of reset"),
+                (
+                    "escaped(1)(2)",
+                    "continuation called after its callcc returned",
+                ),
+                // Resuming runs the body again, which would add 100 once more
+                (
+                    "refs_before(ref(2))",
+                    "cannot read a reference before the shift or perform",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_effects() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "effect Ask : () -> Int",
+                "effect Log : (Int) -> ()",
+                "effect Choose : (Int, Int) -> Int",
+                "fn ask_twice() { perform Ask() + perform Ask() }",
+                "fn logged(x) { perform Log(x) + x }",
+                "fn answer() { handle ask_twice() with { Ask() k => k(21) } }",
+                "fn all_sums() { handle perform Choose(1, 2) + perform Choose(10, 20) with { Choose(a, b) k => k(a) + k(b) } }",
+                "fn then(a, b) { b }",
+                "fn count_asks(r) { handle then(set(r, get(r) + 1), perform Ask()) with { Ask() k => k(1) + k(1) } }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                (
+                    "handle perform Ask() + 1 with { Ask() k => k(41) }",
+                    Value::Int(42),
+                ),
+                ("answer()", Value::Int(42)),
+                // Every combination of choices is tried
+                ("all_sums()", Value::Int(66)),
+                // A handler that does not resume gives the result of the handle
+                ("handle ask_twice() with { Ask() k => 7 }", Value::Int(7)),
+                (
+                    "handle logged(5) with { Log(x) k => k(x) + x, Ask() k => k(0) }",
+                    Value::Int(15),
+                ),
+                // The closest handler of an effect handles it
+                (
+                    "handle handle perform Ask() with { Ask() k => k(1) } + perform Ask() with { Ask() k => k(100) }",
+                    Value::Int(101),
+                ),
+            ],
+        );
+
+        assert_raises(
+            &mut interpreter,
+            &[
+                ("ask_twice()", "unhandled effect `Ask`"),
+                // Resuming runs the body again, which would count once more
+                ("count_asks(ref(0))", "cannot read a reference"),
+            ],
+        );
+
+        assert_rejects(
+            &mut interpreter,
+            &[
+                ("perform Missing()", "Effect 'Missing' is not declared"),
+                (
+                    "perform Log()",
+                    "Effect 'Log' expected 1 arguments but got 0",
+                ),
+                (
+                    "handle 1 with { Choose(a) k => k(a) }",
+                    "Effect 'Choose' expected 2 arguments but got 1",
+                ),
+            ],
+        );
+
+        let mut checker = TypeChecker::new(&interpreter.pool);
+        checker.check_program().unwrap();
+        for (name, row) in [
+            ("ask_twice", vec!["Ask"]),
+            ("logged", vec!["Log"]),
+            ("answer", vec![]),
+            ("all_sums", vec![]),
+        ] {
+            let name_idx = interpreter.pool.get_name_idx_from_func(name).unwrap();
+            assert_eq!(checker.effect_row(name_idx), row, "{}", name);
+        }
+
+        let code = interpreter.pretty_print_function("answer").unwrap();
+        assert!(
+            code.contains("handle ask_twice() with { Ask() l2p0 => l2p0(21) }"),
+            "{}",
+            code
+        );
+        let code = interpreter.pretty_print_function("logged").unwrap();
+        assert!(code.contains("perform Log(x)"), "{}", code);
+
+        let printer = PrettyPrinter::new(&interpreter.pool);
+        assert!(
+            printer
+                .print_effect_defs()
+                .contains("effect Choose : (Int, Int) -> Int\neffect Log : (Int) -> ()"),
+            "{}",
+            printer.print_effect_defs()
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn helper(n) { n * 100 }",
+                "macro twice(e) { e + e }",
+                "macro first_of(a, b) { a }",
+                "macro add_each(xs, e) { collect(map(xs, lambda x { x + e })) }",
+                "macro scaled(e) { helper(e) }",
+                "macro adder(n) { lambda x { x + n } }",
+                "fn quad(x) { twice(twice(x)) }",
+                "fn shifted(x, xs) { add_each(xs, x) }",
+                "fn summed(xs) { add_each(xs, fold(collect(#[1, 2]), 0, lambda acc y { acc + y })) }",
+                "fn use_helper(helper) { scaled(helper) }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("quad(3)", Value::Int(12)),
+                // Arguments are only evaluated where the template uses them
+                ("first_of(5, raise(1))", Value::Int(5)),
+                // The `x` of the template does not capture the one of the caller
+                ("shifted(10, collect(#[1, 2]))", list(ints(&[11, 12]))),
+                ("summed(collect(#[1, 2]))", list(ints(&[4, 5]))),
+                // The parameter `helper` does not capture the function of the template
+                ("use_helper(2)", Value::Int(200)),
+                // Later argument lists apply to the expansion
+                ("adder(2)(3)", Value::Int(5)),
+            ],
+        );
+
+        assert_rejects(
+            &mut interpreter,
+            &[
+                ("twice(1, 2)", "Macro 'twice' expects 1 arguments but got 2"),
+                (
+                    "twice(e: 1)",
+                    "Macro 'twice' cannot be given named arguments",
+                ),
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("quad").unwrap();
+        assert!(code.contains("twice(twice(x))"), "{}", code);
+        let code = interpreter.pretty_print_expanded("quad").unwrap();
+        assert!(code.contains("x + x + (x + x)"), "{}", code);
+
+        let printer = PrettyPrinter::new(&interpreter.pool);
+        let macros = printer.print_macro_defs();
+        assert!(
+            macros.contains("macro add_each(xs, e) { collect(map(xs, (+ e))) }"),
+            "{}",
+            macros
+        );
+    }
+
+    #[test]
+    fn test_quote_and_eval() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn double(n) { n * 2 }",
+                "const BASE = 40",
+                "fn make(x) { quote { x + 1 } }",
+                "fn make_adder(y) { quote { lambda z { z + y } } }",
+                "fn apply(f, v) { f(v) }",
+                "fn nested(x) { apply(lambda y { quote { double(x) + y + BASE } }, 2) }",
+                "fn shadow(x) { eval(make(x * 10)) + x }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("eval(quote { 1 + 2 })", Value::Int(3)),
+                // Parameters keep the values they had where the code was quoted
+                ("eval(make(5))", Value::Int(6)),
+                ("eval(make_adder(3))(4)", Value::Int(7)),
+                ("eval(nested(1))", Value::Int(44)),
+                ("shadow(2)", Value::Int(23)),
+                ("code_kind(make(5))", Value::Str("call".into())),
+                ("code_kind(lift(7))", Value::Str("int".into())),
+                (
+                    "eval(code_call(quote { double }, collect(#[make(20)])))",
+                    Value::Int(42),
+                ),
+            ],
+        );
+
+        // Code is compared by the expression it prints as
+        for (expr, expected) in [
+            ("make(5)", "quote { x + 1 }"),
+            (
+                "code_call(quote { double }, collect(#[lift(3)]))",
+                "quote { double(3) }",
+            ),
+            (
+                "code_call(quote { double }, #[lift(3)])",
+                "quote { double(3) }",
+            ),
+        ] {
+            let result = eval_all(&mut interpreter, &[expr]);
+            assert!(
+                matches!(&result, Value::Code(code) if format!("{:?}", code) == expected),
+                "{} gave {:?}",
+                expr,
+                result
+            );
+        }
+        let result = eval_all(&mut interpreter, &["code_parts(quote { double(4) })"]);
+        let Value::List(parts) = &result else {
+            panic!("code_parts gave {:?}", result);
+        };
+        let parts: Vec<String> = parts.iter().map(|part| format!("{:?}", part)).collect();
+        assert_eq!(parts, ["quote { double } : Code", "quote { 4 } : Code"]);
+
+        assert_raises(
+            &mut interpreter,
+            &[
+                ("eval(1)", "`eval` expects code but got 1 : Int"),
+                (
+                    "code_call(lift(1), collect(#[2]))",
+                    "`code_call` expects code arguments but got 2 : Int",
+                ),
+                (
+                    "code_call(lift(1), #[2])",
+                    "`code_call` expects code arguments but got 2 : Int",
+                ),
+                (
+                    "code_call(lift(1), 2)",
+                    "`code_call` expects a list of arguments",
+                ),
+            ],
+        );
+
+        let code = interpreter.pretty_print_function("make").unwrap();
+        assert!(code.contains("quote { x + 1 }"), "{}", code);
+    }
+
+    #[test]
+    fn test_comptime() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "fn square(x) { x * x }",
+                "fn squares() { comptime { collect(map(range(0, 5), square)) } }",
+                "const LIMIT = comptime { square(12) }",
+                "fn scaled(x) { x * comptime { 6 * 7 } }",
+                "fn answer() { comptime { 6 * 7 } }",
+            ],
+        );
+
+        assert_evals(
+            &mut interpreter,
+            &[
+                ("squares()", list(ints(&[0, 1, 4, 9, 16]))),
+                ("LIMIT", Value::Int(144)),
+                ("scaled(2)", Value::Int(84)),
+                ("comptime { LIMIT + 1 }", Value::Int(145)),
+            ],
+        );
+
+        // The block is kept for printing, the literal of its value runs
+        let pool = &interpreter.pool;
+        let name_idx = pool.get_name_idx_from_func("answer").unwrap();
+        let Ast::FunctionDef { body_idx, .. } = pool[pool.function_defs[&name_idx]] else {
+            panic!("answer is not a function definition");
+        };
+        let Ast::Comptime { value_idx, .. } = pool[body_idx] else {
+            panic!("the block of answer is not evaluated");
+        };
+        assert!(matches!(pool[value_idx], Ast::Integer(42)));
+        let code = interpreter.pretty_print_function("answer").unwrap();
+        assert!(code.contains("comptime { 6 * 7 }"), "{}", code);
+
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &["fn square(x) { x * x }", "fn forever(n) { forever(n + 1) }"],
+        );
+        for (input, error) in [
+            ("fn bad(x) { comptime { x + 1 } }", "only known at runtime"),
+            (
+                "fn boom() { comptime { raise(1) } }",
+                "comptime block at line 1, column 13",
+            ),
+            (
+                "fn endless() { comptime { collect(map(range(0, 1000000000), square)) } }",
+                "step limit exceeded",
+            ),
+            (
+                "fn counted() { comptime { len(collect(0..50000000)) } }",
+                "step limit exceeded",
+            ),
+            (
+                "fn summed() { comptime { fold(0..50000000, 0, (+)) } }",
+                "step limit exceeded",
+            ),
+            (
+                "fn opaque() { comptime { square } }",
+                "cannot be written as a literal",
+            ),
+            (
+                "fn deep() { comptime { forever(0) } }",
+                "recursion too deep",
+            ),
+        ] {
+            let result = interpreter.eval_expression(input);
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains(error)),
+                "{} gave {:?}",
+                input,
+                result
+            );
+
+            // The failed definition is not kept
+            let result = eval_all(&mut interpreter, &["square(3)"]);
+            assert!(
+                matches!(result, Value::Int(9)),
+                "after {}: {:?}",
+                input,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "#[test] fn adds_up() { 1 + 1 == 2 }",
+                "#[test] fn miscounts() { 1 + 1 == 3 }",
+                "#[deprecated(\"use total\")] fn old_sum(a, b) { a + b }",
+                "#[inline] #[memo] fn total(a, b) { a + b }",
+                "#[custom(1, name, \"x\")] fn tagged() { old_sum(1, 2) }",
+            ],
+        );
+        // An array literal is still an expression
+        assert_evals(
+            &mut interpreter,
+            &[("#[1, 2][1]", Value::Int(2)), ("tagged()", Value::Int(3))],
+        );
+
+        let pool = &interpreter.pool;
+        let name_idx = pool.get_name_idx_from_func("old_sum").unwrap();
+        let deprecated = pool.attribute(name_idx, "deprecated").unwrap();
+        assert_eq!(
+            deprecated.args,
+            [AttributeArg::Str("use total".to_string())]
+        );
+        let name_idx = pool.get_name_idx_from_func("total").unwrap();
+        assert!(pool.attribute(name_idx, "inline").is_some());
+        assert!(pool.attribute(name_idx, "test").is_none());
+
+        let mut checker = TypeChecker::new(pool);
+        checker.check_program().unwrap();
+        let mut warnings: Vec<String> = checker.warnings().iter().map(|w| w.to_string()).collect();
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            [
+                "Function 'tagged' has the unknown attribute 'custom'",
+                "Function 'tagged' uses 'old_sum', which is deprecated: use total",
+            ]
+        );
+
+        let code = interpreter.pretty_print_function("tagged").unwrap();
+        assert!(
+            code.starts_with("#[custom(1, name, \"x\")]\nfn tagged()"),
+            "{}",
+            code
+        );
+        let code = interpreter.pretty_print_function("total").unwrap();
+        assert!(
+            code.starts_with("#[inline]\n#[memo]\nfn total("),
+            "{}",
+            code
+        );
+
+        let results = interpreter.run_tests();
+        assert_eq!(
+            results,
+            [
+                ("adds_up".to_string(), Ok(())),
+                ("miscounts".to_string(), Err("returned false".to_string())),
+            ]
+        );
+
+        // Attributes belong to a definition, not to the name
+        eval_all(&mut interpreter, &["fn miscounts() { 1 }"]);
+        assert_eq!(interpreter.run_tests().len(), 1);
+    }
+
+    #[test]
+    fn test_memo() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "#[memo] fn fib(n) | n < 2 = n | _ = fib(n + -1) + fib(n + -2)",
+                "#[memo] fn pick(f, x) { f(x) }",
+            ],
+        );
+        assert_eq!(
+            interpreter.memo_cache_sizes(),
+            [("fib".to_string(), 0), ("pick".to_string(), 0)]
+        );
+
+        // Without the cache this would take ages
+        assert_evals(
+            &mut interpreter,
+            &[("fib(80)", Value::Int(23416728348467685))],
+        );
+        // The results stay cached across inputs
+        eval_all(&mut interpreter, &["fib(10)"]);
+        assert_eq!(
+            interpreter.memo_cache_sizes(),
+            [("fib".to_string(), 81), ("pick".to_string(), 0)]
+        );
+
+        // Functions cannot be keys, such calls are not cached
+        assert_evals(
+            &mut interpreter,
+            &[("pick(lambda x { x * 2 }, 4)", Value::Int(8))],
+        );
+        assert_eq!(
+            interpreter.memo_cache_sizes(),
+            [("fib".to_string(), 81), ("pick".to_string(), 0)]
+        );
+
+        interpreter.clear_memo_caches();
+        assert_eq!(
+            interpreter.memo_cache_sizes(),
+            [("fib".to_string(), 0), ("pick".to_string(), 0)]
+        );
+
+        // A new definition starts without the old results
+        eval_all(&mut interpreter, &["#[memo] fn fib(n) { n }", "fib(3)"]);
+        assert_eq!(
+            interpreter.memo_cache_sizes(),
+            [("fib".to_string(), 1), ("pick".to_string(), 0)]
+        );
+
+        // Lists and arrays with the same items are cached apart
+        eval_all(
+            &mut interpreter,
+            &["#[memo] fn idm(x) { x }", "idm(#[1, 2])"],
+        );
+        assert_evals(
+            &mut interpreter,
+            &[("idm(collect(1..3))", list(ints(&[1, 2])))],
+        );
+
+        // Memoizing functions that do more than compute their result warns
+        eval_all(
+            &mut interpreter,
+            &[
+                "effect Log : (Int) -> ()",
+                "#[memo] fn logged(x) { perform Log(x) + x }",
+                "#[memo] fn counted(r) { set(r, get(r) + 1) }",
+            ],
+        );
+        let mut checker = TypeChecker::new(&interpreter.pool);
+        checker.check_program().unwrap();
+        let warnings: Vec<String> = checker.warnings().iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            [
+                "Function 'counted' is memoized but uses mutable references, which cached calls skip",
+                "Function 'logged' is memoized but performs Log, which cached calls skip",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let mut interpreter = Interpreter::new();
+        eval_all(
+            &mut interpreter,
+            &[
+                "/// Adds one.\n///\n/// Works on any `Int`.\n#[memo]\nfn inc(x) { x + 1 /// not a doc\n }",
+                "fn plain(x) { x } // A plain comment\n",
+                "/// Stray before a constant\nconst TEN = 10",
+                // Only doc comments before a top-level function are docs
+                "fn pair(u) {\n /// note\n #[1, 2]\n}",
+                "const ONE = 1\n/// After a constant\nfn after_const() { ONE }\n/// After a function\nfn after_fn() { 2 }",
+            ],
+        );
+        assert_eq!(
+            interpreter.function_doc("inc"),
+            Ok(Some("Adds one.\n\nWorks on any `Int`."))
+        );
+        assert_eq!(interpreter.function_doc("plain"), Ok(None));
+        assert_eq!(interpreter.function_doc("pair"), Ok(None));
+        assert_eq!(
+            interpreter.function_doc("after_const"),
+            Ok(Some("After a constant"))
+        );
+        assert_eq!(
+            interpreter.function_doc("after_fn"),
+            Ok(Some("After a function"))
+        );
+        assert_evals(&mut interpreter, &[("pair(0)[1]", Value::Int(2))]);
+        assert!(interpreter.function_doc("missing").is_err());
+
+        let code = interpreter.pretty_print_function("inc").unwrap();
+        assert!(
+            code.starts_with("/// Adds one.\n///\n/// Works on any `Int`.\n#[memo]\nfn inc("),
+            "{}",
+            code
+        );
+
+        // The docs survive saving and loading the program
+        let saved = PrettyPrinter::new(&interpreter.pool).print_all_functions();
+        let mut reloaded = Interpreter::new();
+        eval_all(&mut reloaded, &[&saved]);
+        assert_eq!(
+            reloaded.function_doc("inc"),
+            Ok(Some("Adds one.\n\nWorks on any `Int`."))
+        );
+        assert_evals(&mut reloaded, &[("inc(TEN)", Value::Int(11))]);
+
+        // Docs belong to a definition, not to the name
+        eval_all(&mut interpreter, &["fn inc(x) { x + 2 }"]);
+        assert_eq!(interpreter.function_doc("inc"), Ok(None));
+    }
+}
@@ -1,11 +1,20 @@
-use pest::iterators::Pair;
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
 use std::collections::HashMap;
+use std::iter::Peekable;
 
 use super::error::{error_with_location, ParserError, Result};
+use crate::ast::attributes::{Attribute, AttributeArg};
+use crate::ast::comptime::ComptimeBlock;
+use crate::ast::effects::EffectDecl;
 use crate::ast::indices::AstIdx;
+use crate::ast::macros::MacroDef;
+use crate::ast::operators::{is_builtin_operator, Fixity, OperatorDef};
+use crate::ast::params::{NamedArgs, Param};
 use crate::ast::pool::AstPool;
+use crate::ast::primitives::PrimitiveFunc;
+use crate::ast::Ast;
 use crate::NameIdx;
 
 #[derive(Parser)]
@@ -63,8 +72,41 @@ pub fn parse_program(input: &str, pool: &mut AstPool) -> Result<Vec<AstIdx>> {
     for pair in pairs {
         match pair.as_rule() {
             Rule::program => {
-                for inner_pair in pair.into_inner() {
+                let items: Vec<Pair<Rule>> = pair.into_inner().collect();
+                let operator_defs: Vec<Pair<Rule>> = items
+                    .iter()
+                    .filter(|p| p.as_rule() == Rule::operator_def)
+                    .cloned()
+                    .collect();
+
+                // Operators are registered up front so functions can use
+                // operators declared further down in the file
+                for item in &operator_defs {
+                    parse_operator_def(item.clone(), pool)?;
+                }
+
+                // Likewise parameters, so that calls can name them and leave
+                // out defaulted ones before the function is parsed
+                for item in items.iter().filter(|p| p.as_rule() == Rule::function_def) {
+                    parse_function_signature(item.clone(), pool, &scope)?;
+                }
+
+                // Macros are expanded while parsing, so they are defined
+                // before the functions. A macro can use the ones before it.
+                for item in items.iter().filter(|p| p.as_rule() == Rule::macro_def) {
+                    parse_macro_def(item.clone(), pool, &scope)?;
+                }
+
+                for inner_pair in items {
                     match inner_pair.as_rule() {
+                        Rule::operator_def | Rule::macro_def => {}
+                        Rule::effect_def => {
+                            parse_effect_def(inner_pair, pool)?;
+                        }
+                        Rule::const_def => {
+                            let const_def = parse_const_def(inner_pair, pool, &mut scope)?;
+                            top_level_nodes.push(const_def);
+                        }
                         Rule::function_def => {
                             let function_def = parse_function_def(inner_pair, pool, &mut scope)?;
                             top_level_nodes.push(function_def);
@@ -73,6 +115,11 @@ pub fn parse_program(input: &str, pool: &mut AstPool) -> Result<Vec<AstIdx>> {
                         _ => return Err(ParserError::UnexpectedRule(inner_pair.as_rule())),
                     }
                 }
+
+                // The functions of the operators may be defined after them
+                for item in operator_defs {
+                    check_operator_function(item, pool)?;
+                }
             }
             Rule::EOI => {}
             _ => return Err(ParserError::UnexpectedRule(pair.as_rule())),
@@ -82,7 +129,182 @@ pub fn parse_program(input: &str, pool: &mut AstPool) -> Result<Vec<AstIdx>> {
     Ok(top_level_nodes)
 }
 
-fn parse_function_def(
+fn parse_macro_def(pair: Pair<Rule>, pool: &mut AstPool, parent_scope: &Scope) -> Result<NameIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut pairs: Vec<Pair<Rule>> = pair.into_inner().collect();
+
+    let (Some(template), true) = (pairs.pop(), !pairs.is_empty()) else {
+        return Err(error_with_location(
+            input,
+            span,
+            "Macro definition is missing its name or template",
+        ));
+    };
+    let name = pairs.remove(0);
+
+    // The arguments are parameters of the template, replaced on expansion
+    let mut macro_scope = parent_scope.create_child();
+    let mut params = Vec::new();
+    for (i, param) in pairs.iter().enumerate() {
+        let param_name_idx = pool.intern_string(param.as_str());
+        macro_scope.add_param(param.as_str(), i, param_name_idx);
+        params.push(param_name_idx);
+    }
+    let body_idx = parse_expr(template, pool, &macro_scope)?;
+
+    Ok(pool.add_macro_def(name.as_str(), MacroDef { params, body_idx }))
+}
+
+fn parse_effect_def(pair: Pair<Rule>, pool: &mut AstPool) -> Result<NameIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut names: Vec<&str> = pair.into_inner().map(|p| p.as_str()).collect();
+
+    if names.len() < 2 {
+        return Err(error_with_location(
+            input,
+            span,
+            "Effect declaration is missing its name or result type",
+        ));
+    }
+    let name = names.remove(0);
+    let result = pool.intern_string(names.pop().unwrap_or_default());
+    let params = names
+        .iter()
+        .map(|param| pool.intern_string(param))
+        .collect();
+
+    Ok(pool.add_effect_def(name, EffectDecl { params, result }))
+}
+
+fn parse_operator_def(pair: Pair<Rule>, pool: &mut AstPool) -> Result<NameIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut inner_pairs = pair.into_inner();
+    let mut next = |what: &str| {
+        inner_pairs.next().ok_or_else(|| {
+            error_with_location(
+                input,
+                span,
+                &format!("Operator declaration is missing its {}", what),
+            )
+        })
+    };
+
+    let fixity = match next("fixity")?.as_str() {
+        "infixl" => Fixity::Left,
+        "infixr" => Fixity::Right,
+        _ => Fixity::None,
+    };
+
+    let precedence_pair = next("precedence")?;
+    let precedence = precedence_pair.as_str().parse::<u8>().map_err(|_| {
+        error_with_location(
+            input,
+            precedence_pair.as_span(),
+            &format!(
+                "Invalid operator precedence '{}' - expected a number between 0 and 255",
+                precedence_pair.as_str()
+            ),
+        )
+    })?;
+
+    let symbol_pair = next("symbol")?;
+    let symbol = symbol_pair.as_str();
+    if is_builtin_operator(symbol) {
+        return Err(error_with_location(
+            input,
+            symbol_pair.as_span(),
+            &format!("Cannot redefine built-in operator '{}'", symbol),
+        ));
+    }
+
+    let func_name = next("function name")?.as_str();
+
+    // Declaring the same operator again keeps the code using it printable
+    if let Some(existing) = pool.get_operator(symbol) {
+        let existing_func = pool.get_string(existing.func_name);
+        if existing.fixity != fixity
+            || existing.precedence != precedence
+            || existing_func != func_name
+        {
+            return Err(error_with_location(
+                input,
+                symbol_pair.as_span(),
+                &format!(
+                    "Operator '{}' is already declared as `{} {} {} = {}`",
+                    symbol,
+                    existing.fixity.keyword(),
+                    existing.precedence,
+                    symbol,
+                    existing_func
+                ),
+            ));
+        }
+    }
+
+    Ok(pool.add_operator_def(symbol, fixity, precedence, func_name))
+}
+
+/// Fail if the function an operator is declared for is neither a primitive
+/// nor a function or constant of the program
+fn check_operator_function(pair: Pair<Rule>, pool: &AstPool) -> Result<()> {
+    let input = pair.as_str();
+    let mut inner_pairs = pair.into_inner();
+    let (Some(symbol_pair), Some(func_pair)) = (inner_pairs.nth(2), inner_pairs.next()) else {
+        return Ok(());
+    };
+
+    let func_name = func_pair.as_str();
+    let defined = pool.get_primitive_func(func_name).is_some()
+        || pool
+            .get_name_idx_from_func(func_name)
+            .and_then(|name_idx| pool.get_global_def(name_idx))
+            .is_some();
+    if !defined {
+        return Err(error_with_location(
+            input,
+            func_pair.as_span(),
+            &format!(
+                "Operator '{}' is declared for the unknown function '{}'",
+                symbol_pair.as_str(),
+                func_name
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Constants and functions share one namespace with the primitives, so a
+/// name can only be redefined as the same kind of item and never shadow a
+/// primitive, which calls would keep resolving to
+fn check_not_defined(
+    input: &str,
+    span: pest::Span<'_>,
+    pool: &AstPool,
+    other_defs: &HashMap<NameIdx, AstIdx>,
+    other_kind: &str,
+) -> Result<()> {
+    let name = span.as_str();
+    if pool.get_primitive_func(name).is_some() {
+        return Err(error_with_location(
+            input,
+            span,
+            &format!("'{}' is a built-in function and cannot be redefined", name),
+        ));
+    }
+    match pool.get_name_idx_from_func(name) {
+        Some(name_idx) if other_defs.contains_key(&name_idx) => Err(error_with_location(
+            input,
+            span,
+            &format!("'{}' is already defined as {}", name, other_kind),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn parse_const_def(
     pair: Pair<Rule>,
     pool: &mut AstPool,
     parent_scope: &mut Scope,
@@ -91,6 +313,160 @@ fn parse_function_def(
     let span = pair.as_span();
     let mut inner_pairs = pair.into_inner();
 
+    let identifier = inner_pairs.next().ok_or_else(|| {
+        error_with_location(input, span, "Constant definition is missing its name")
+    })?;
+    let expr_pair = inner_pairs.next().ok_or_else(|| {
+        error_with_location(input, span, "Constant definition is missing its value")
+    })?;
+
+    check_not_defined(
+        input,
+        identifier.as_span(),
+        pool,
+        &pool.function_defs,
+        "a function",
+    )?;
+
+    // The value is evaluated like the body of a function without parameters
+    let const_scope = parent_scope.create_child();
+    let body_idx = parse_expr(expr_pair, pool, &const_scope)?;
+
+    Ok(pool.add_const_def(identifier.as_str(), body_idx))
+}
+
+fn parse_function_signature(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<()> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut inner_pairs = pair
+        .into_inner()
+        .skip_while(|p| p.as_rule() == Rule::attribute);
+
+    let (Some(identifier), Some(param_list)) = (inner_pairs.next(), inner_pairs.next()) else {
+        return Err(error_with_location(
+            input,
+            span,
+            "Function definition is missing its name or parameter list",
+        ));
+    };
+
+    let param_pairs: Vec<Pair<Rule>> = param_list.into_inner().collect();
+
+    // Defaults are evaluated when the function is called, in a scope of the
+    // function's parameters so that using one is reported by its name
+    let mut default_scope = scope.create_child();
+    for (i, param_pair) in param_pairs.iter().enumerate() {
+        if let Some(identifier) = param_pair.clone().into_inner().next() {
+            let name_idx = pool.intern_string(identifier.as_str());
+            default_scope.add_param(identifier.as_str(), i, name_idx);
+        }
+    }
+
+    let mut params: Vec<Param> = Vec::new();
+    let mut rest = None;
+
+    for (i, param_pair) in param_pairs.into_iter().enumerate() {
+        let param_span = param_pair.as_span();
+        let is_rest = param_pair.as_rule() == Rule::rest_param;
+        let mut parts = param_pair.into_inner();
+        let param_name = parts.next().map(|p| p.as_str()).unwrap_or_default();
+        let name = pool.intern_string(param_name);
+
+        if let Some(prev_idx) = params.iter().position(|param| param.name == name) {
+            return Err(error_with_location(
+                input,
+                span,
+                &format!(
+                    "Duplicate parameter name '{}' at positions {} and {}",
+                    param_name,
+                    prev_idx + 1,
+                    i + 1
+                ),
+            ));
+        }
+
+        let default = match parts.next() {
+            Some(expr_pair) => {
+                let start = pool.nodes.len();
+                let default_idx = parse_expr(expr_pair, pool, &default_scope)?;
+                let used_param = pool.nodes[start..].iter().find_map(|node| match node {
+                    Ast::ParamRef { name, level: 1, .. } => Some(*name),
+                    _ => None,
+                });
+                if let Some(used_param) = used_param {
+                    return Err(error_with_location(
+                        input,
+                        param_span,
+                        &format!(
+                            "Default value of '{}' cannot use parameter '{}'",
+                            param_name,
+                            pool.get_string(used_param)
+                        ),
+                    ));
+                }
+                Some(default_idx)
+            }
+            None => None,
+        };
+
+        if !is_rest && default.is_none() && params.iter().any(|param| param.default.is_some()) {
+            return Err(error_with_location(
+                input,
+                param_span,
+                &format!(
+                    "Parameter '{}' needs a default value as it follows parameters with one",
+                    param_name
+                ),
+            ));
+        }
+
+        // The rest parameter cannot be named or defaulted in calls
+        if is_rest {
+            rest = Some(name);
+        } else {
+            params.push(Param { name, default });
+        }
+    }
+
+    pool.add_function_params(identifier.as_str(), params, rest);
+    Ok(())
+}
+
+/// The text of the `///` comments right above a function definition, with
+/// only blank lines between them and the definition. The grammar skips them
+/// like any other comment.
+fn doc_lines_before(span: pest::Span<'_>) -> Vec<&str> {
+    let mut lines = span.get_input()[..span.start()].rsplit('\n');
+    // The definition follows other code on its line
+    if lines.next().is_some_and(|line| !line.trim().is_empty()) {
+        return Vec::new();
+    }
+
+    let mut doc_lines: Vec<&str> = lines
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with("///"))
+        .filter_map(|line| line.strip_prefix("///"))
+        .map(|text| text.strip_prefix(' ').unwrap_or(text).trim_end())
+        .collect();
+    doc_lines.reverse();
+    doc_lines
+}
+
+fn parse_function_def(
+    pair: Pair<Rule>,
+    pool: &mut AstPool,
+    parent_scope: &mut Scope,
+) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let doc_lines = doc_lines_before(span);
+    let mut inner_pairs = pair.into_inner().peekable();
+
+    let mut attributes = Vec::new();
+    while let Some(attribute) = inner_pairs.next_if(|p| p.as_rule() == Rule::attribute) {
+        attributes.push(parse_attribute(attribute, pool)?);
+    }
+
     let identifier = inner_pairs.next().ok_or_else(|| {
         error_with_location(input, span, "Function definition is missing function name")
     })?;
@@ -106,8 +482,26 @@ fn parse_function_def(
         ));
     }
 
+    check_not_defined(
+        input,
+        identifier.as_span(),
+        pool,
+        &pool.const_defs,
+        "a constant",
+    )?;
+
     let func_name = identifier.as_str();
     let func_name_idx = pool.intern_string(func_name);
+    if attributes.is_empty() {
+        pool.attributes.remove(&func_name_idx);
+    } else {
+        pool.attributes.insert(func_name_idx, attributes);
+    }
+    if doc_lines.is_empty() {
+        pool.docs.remove(&func_name_idx);
+    } else {
+        pool.docs.insert(func_name_idx, doc_lines.join("\n"));
+    }
 
     // Get parameter list
     let param_list = inner_pairs.next().ok_or_else(|| error_with_location(
@@ -125,42 +519,20 @@ fn parse_function_def(
         ));
     }
 
-    // Create a new scope for this function's parameters
+    // Create a new scope for this function's parameters, which were
+    // validated when the signature was registered
     let mut function_scope = parent_scope.create_child();
-
-    // Collect and validate parameters
     let param_pairs: Vec<Pair<Rule>> = param_list.into_inner().collect();
-    let mut seen_params = HashMap::new();
-
-    for (i, param_pair) in param_pairs.iter().enumerate() {
-        if param_pair.as_rule() != Rule::identifier {
-            return Err(error_with_location(
-                input,
-                param_pair.as_span(),
-                &format!(
-                    "Expected parameter name but found {:?}",
-                    param_pair.as_rule()
-                ),
-            ));
-        }
-
-        let param_name = param_pair.as_str();
+    let param_count = param_pairs.len();
+    let variadic = param_pairs
+        .last()
+        .is_some_and(|pair| pair.as_rule() == Rule::rest_param);
 
-        if let Some(prev_idx) = seen_params.get(param_name) {
-            return Err(error_with_location(
-                input,
-                span,
-                &format!(
-                    "Duplicate parameter name '{}' at positions {} and {}",
-                    param_name,
-                    prev_idx + 1,
-                    i + 1
-                ),
-            ));
-        }
-        seen_params.insert(param_name, i);
-
-        // Add parameter to function scope
+    for (i, param_pair) in param_pairs.into_iter().enumerate() {
+        let Some(identifier) = param_pair.into_inner().next() else {
+            continue;
+        };
+        let param_name = identifier.as_str();
         let param_name_idx = pool.intern_string(param_name);
         function_scope.add_param(param_name, i, param_name_idx);
     }
@@ -170,7 +542,7 @@ fn parse_function_def(
         input, span, "Function definition is missing body - function must contain an expression between curly braces"
     ))?;
 
-    if expr_pair.as_rule() != Rule::expr {
+    if expr_pair.as_rule() != Rule::function_body {
         return Err(error_with_location(
             input,
             expr_pair.as_span(),
@@ -182,9 +554,170 @@ fn parse_function_def(
     }
 
     // Parse the function body using the function scope
-    let body_idx = parse_expr(expr_pair, pool, &function_scope)?;
+    let body_idx = match inner_pairs.next() {
+        Some(where_pair) => {
+            let (body_idx, names) =
+                parse_where_clause(expr_pair, where_pair, pool, &function_scope, func_name_idx)?;
+            pool.where_bindings.insert(func_name_idx, names);
+            body_idx
+        }
+        None => {
+            pool.where_bindings.remove(&func_name_idx);
+            parse_function_body(expr_pair, pool, &function_scope, func_name_idx)?
+        }
+    };
+
+    Ok(pool.add_function_def(func_name, param_count, variadic, body_idx))
+}
+
+/// An attribute of a function, e.g. `#[deprecated("use sum")]`
+fn parse_attribute(pair: Pair<Rule>, pool: &mut AstPool) -> Result<Attribute> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut inner_pairs = pair.into_inner();
+
+    let name = inner_pairs
+        .next()
+        .ok_or_else(|| error_with_location(input, span, "Attribute is missing its name"))?;
+    let args = inner_pairs
+        .map(|arg| match arg.as_rule() {
+            Rule::integer => arg.as_str().parse().map(AttributeArg::Int).map_err(|_| {
+                error_with_location(
+                    input,
+                    arg.as_span(),
+                    &format!("Invalid integer literal: '{}'", arg.as_str()),
+                )
+            }),
+            Rule::string_literal => {
+                let quoted = arg.as_str();
+                Ok(AttributeArg::Str(quoted[1..quoted.len() - 1].to_string()))
+            }
+            _ => Ok(AttributeArg::Name(pool.intern_string(arg.as_str()))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Attribute {
+        name: pool.intern_string(name.as_str()),
+        args,
+    })
+}
+
+/// Parse a function body, either an expression in braces or guarded clauses
+fn parse_function_body(
+    pair: Pair<Rule>,
+    pool: &mut AstPool,
+    scope: &Scope,
+    func_name_idx: NameIdx,
+) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let clauses: Vec<Pair<Rule>> = pair.into_inner().collect();
+
+    if let [expr_pair] = clauses.as_slice()
+        && expr_pair.as_rule() == Rule::expr
+    {
+        return parse_expr(expr_pair.clone(), pool, scope);
+    }
+
+    let old_len = pool.nodes.len();
+    let mut child_count = 0;
+
+    for (i, clause) in clauses.iter().enumerate() {
+        let mut parts = clause.clone().into_inner();
+        let (Some(guard), Some(value)) = (parts.next(), parts.next()) else {
+            return Err(error_with_location(
+                input,
+                span,
+                "Incomplete guarded clause",
+            ));
+        };
+
+        if guard.as_rule() == Rule::catch_all {
+            if i + 1 != clauses.len() {
+                return Err(error_with_location(
+                    input,
+                    clause.as_span(),
+                    "The catch-all clause '_' must be the last clause",
+                ));
+            }
+        } else {
+            parse_expr(guard, pool, scope)?;
+            child_count += 1;
+        }
+
+        parse_expr(value, pool, scope)?;
+        child_count += 1;
+    }
+
+    let child_start = AstIdx(pool.nodes.len() - 1);
+    let len = pool.nodes.len() - old_len;
+    Ok(pool.add_guards(func_name_idx, child_start, child_count, len))
+}
+
+/// Parse a function body with a `where` clause as nested calls of lambdas
+/// taking one binding each, i.e. `body where a = e, h(x) { f }` becomes
+/// `(lambda a { (lambda h { body })(lambda x { f }) })(e)`.
+/// The bindings are evaluated once per call, in order, and can use the
+/// function's parameters and the bindings before them.
+fn parse_where_clause(
+    body_pair: Pair<Rule>,
+    where_pair: Pair<Rule>,
+    pool: &mut AstPool,
+    function_scope: &Scope,
+    func_name_idx: NameIdx,
+) -> Result<(AstIdx, Vec<NameIdx>)> {
+    let input = where_pair.as_str();
+
+    // Each binding is the argument of a lambda whose body holds the later
+    // bindings and the function body, so a binding can use the ones before
+    let mut scope = function_scope.clone();
+    let mut names: Vec<NameIdx> = Vec::new();
+    let mut bindings: Vec<(usize, AstIdx)> = Vec::new();
+
+    for binding in where_pair.into_inner() {
+        let is_helper = binding.as_rule() == Rule::where_helper;
+        let mut parts: Vec<Pair<Rule>> = binding.into_inner().collect();
+        let (Some(body), Some(identifier)) = (parts.pop(), parts.first().cloned()) else {
+            continue;
+        };
+
+        let name = identifier.as_str();
+        let name_idx = pool.intern_string(name);
+        if names.contains(&name_idx) {
+            return Err(error_with_location(
+                input,
+                identifier.as_span(),
+                &format!("Duplicate local definition '{}' in where clause", name),
+            ));
+        }
+
+        let start = pool.nodes.len();
+        let binding_idx = if is_helper {
+            let mut helper_scope = scope.create_child();
+            for (offset, param) in parts[1..].iter().enumerate() {
+                let param_name_idx = pool.intern_string(param.as_str());
+                helper_scope.add_param(param.as_str(), offset, param_name_idx);
+            }
+            let helper_body = parse_expr(body, pool, &helper_scope)?;
+            pool.add_lambda(parts.len() - 1, helper_body)
+        } else {
+            parse_expr(body, pool, &scope)?
+        };
+        bindings.push((start, binding_idx));
 
-    Ok(pool.add_function_def(func_name, param_pairs.len(), body_idx))
+        scope = scope.create_child();
+        scope.add_param(name, 0, name_idx);
+        names.push(name_idx);
+    }
+
+    let mut body_idx = parse_function_body(body_pair, pool, &scope, func_name_idx)?;
+    for &(start, binding_idx) in bindings.iter().rev() {
+        let lambda_idx = pool.add_lambda(1, body_idx);
+        let len = pool.nodes.len() - start;
+        body_idx = pool.add_lambda_call(lambda_idx, binding_idx, 1, len);
+    }
+
+    Ok((body_idx, names))
 }
 
 fn parse_expr(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
@@ -192,21 +725,14 @@ fn parse_expr(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<Ast
     let span = pair.as_span();
 
     match pair.as_rule() {
-        Rule::expr => {
-            // Unwrap the expr to get to the add_expr inside
-            let inner = pair.into_inner().next().ok_or_else(|| {
-                error_with_location(input, span, "Empty expression where a value was expected")
-            })?;
-            parse_expr(inner, pool, scope)
-        }
-        Rule::add_expr => parse_binary_expr(pair, pool, scope),
-        Rule::mul_expr => parse_binary_expr(pair, pool, scope),
+        Rule::expr => parse_binary_expr(pair, pool, scope),
         Rule::primary => {
-            let inner = pair.into_inner().next().ok_or_else(|| {
+            let mut inner_pairs = pair.into_inner();
+            let inner = inner_pairs.next().ok_or_else(|| {
                 error_with_location(input, span, "Empty expression where a value was expected")
             })?;
 
-            match inner.as_rule() {
+            let mut node_idx = match inner.as_rule() {
                 Rule::integer => {
                     let int_span = inner.as_span();
                     let value = inner.as_str().parse::<i64>().map_err(|_| {
@@ -220,28 +746,67 @@ fn parse_expr(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<Ast
                 }
                 Rule::identifier => {
                     let name = inner.as_str();
-                    let id_span = inner.as_span();
 
                     if let Some((offset, (level, name_idx))) = scope.lookup(name) {
                         // Using updated ParamRef with name, level, and offset
                         Ok(pool.add_param_ref(name_idx, level, offset))
+                    } else if let Some(func) = pool.get_primitive_func(name) {
+                        Ok(pool.add_primitive_func(func))
                     } else {
-                        Err(error_with_location(
-                            input,
-                            id_span,
-                            &format!("Undefined variable '{}' - all variables must be function parameters", name)
-                        ))
+                        // Functions are first-class, the checker reports unknown names
+                        Ok(pool.add_user_func(name))
                     }
                 }
                 Rule::function_call => parse_function_call(inner, pool, scope),
                 Rule::lambda => parse_lambda(inner, pool, scope),
-                Rule::expr => parse_expr(inner, pool, scope),
+                Rule::try_catch => parse_try_catch(inner, pool, scope),
+                Rule::array_literal => {
+                    parse_collection_literal(inner, PrimitiveFunc::ArrayNew, pool, scope)
+                }
+                Rule::lazy_expr => parse_lazy(inner, pool, scope),
+                Rule::quote_expr => parse_quote(inner, pool, scope),
+                Rule::comptime_expr => parse_comptime(inner, pool, scope),
+                Rule::yield_expr => parse_yield(inner, pool, scope),
+                Rule::perform_expr => parse_perform(inner, pool, scope),
+                Rule::handle_expr => parse_handle(inner, pool, scope),
+                Rule::map_literal => {
+                    parse_collection_literal(inner, PrimitiveFunc::MapNew, pool, scope)
+                }
+                Rule::operator_section => parse_operator_section(inner, pool, scope),
+                Rule::parenthesized => parse_parenthesized(inner, pool, scope),
                 _ => Err(error_with_location(
                     input,
                     inner.as_span(),
                     &format!("Unexpected syntax element: {:?}", inner.as_rule()),
                 )),
+            }?;
+
+            // The remaining pairs are postfix `?`, `[i]` and call operators
+            for postfix in inner_pairs {
+                node_idx = match postfix.as_rule() {
+                    Rule::call_operator => {
+                        // The callee comes first, like in calls of calls
+                        let start = node_idx.0 + 1 - pool.len(node_idx);
+                        let args_pair = postfix.into_inner().next().ok_or_else(|| {
+                            error_with_location(input, span, "Call is missing its arguments")
+                        })?;
+                        let (arg_count, _) = parse_arguments(args_pair, None, pool, scope)?;
+                        let child_start = AstIdx(pool.nodes.len() - 1);
+                        let len = pool.nodes.len() - start;
+                        pool.add_lambda_call(node_idx, child_start, arg_count, len)
+                    }
+                    _ => match postfix.into_inner().next() {
+                        Some(index) => {
+                            let index_idx = parse_expr(index, pool, scope)?;
+                            let total_len = pool.len(node_idx) + pool.len(index_idx) + 1;
+                            let func_idx = pool.add_primitive_func(PrimitiveFunc::ArrayIndex);
+                            pool.add_call(func_idx, index_idx, 2, total_len)
+                        }
+                        None => pool.add_try(node_idx),
+                    },
+                };
             }
+            Ok(node_idx)
         }
         _ => Err(error_with_location(
             input,
@@ -296,72 +861,355 @@ fn parse_lambda(pair: Pair<Rule>, pool: &mut AstPool, parent_scope: &Scope) -> R
     Ok(pool.add_lambda(param_count, body_idx))
 }
 
-fn parse_binary_expr(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+/// `lazy e` is a call to the `lazy` primitive with a lambda without
+/// parameters computing `e`, which captures what `e` uses
+fn parse_lazy(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
     let input = pair.as_str();
     let span = pair.as_span();
-    let mut pairs = pair.into_inner();
+    let expr = pair
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::expr)
+        .ok_or_else(|| error_with_location(input, span, "lazy is missing its expression"))?;
 
-    // Parse the first operand
-    let first = pairs.next().ok_or_else(|| {
-        error_with_location(input, span, "Binary expression is missing its left operand")
-    })?;
+    let lambda_scope = scope.create_child();
+    let body_idx = parse_expr(expr, pool, &lambda_scope)?;
+    let lambda_idx = pool.add_lambda(0, body_idx);
 
-    let mut left = parse_expr(first, pool, scope)?;
+    let total_len = pool.len(lambda_idx) + 1;
+    let func_idx = pool.add_primitive_func(PrimitiveFunc::Lazy);
+    Ok(pool.add_call(func_idx, lambda_idx, 1, total_len))
+}
 
-    // If there are no operators, just return the first operand
-    if pairs.peek().is_none() {
-        return Ok(left);
-    }
+/// `quote { e }` is a call to the `quote` primitive with a lambda computing
+/// `e`, which keeps the parameters `e` uses in scope
+fn parse_quote(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let expr = pair
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::expr)
+        .ok_or_else(|| error_with_location(input, span, "quote is missing its expression"))?;
+
+    let lambda_scope = scope.create_child();
+    let body_idx = parse_expr(expr, pool, &lambda_scope)?;
+    let lambda_idx = pool.add_lambda(0, body_idx);
 
-    // Process operators and right operands
-    while let Some(op) = pairs.next() {
-        let op_span = op.as_span();
+    let total_len = pool.len(lambda_idx) + 1;
+    let func_idx = pool.add_primitive_func(PrimitiveFunc::Quote);
+    Ok(pool.add_call(func_idx, lambda_idx, 1, total_len))
+}
 
-        if op.as_rule() != Rule::add_op && op.as_rule() != Rule::mul_op {
+/// `comptime { e }` is a call to the `comptime` primitive with a lambda
+/// computing `e`, replaced by its value when the program is loaded. The
+/// parameters in scope have no value by then.
+fn parse_comptime(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let expr = pair
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::expr)
+        .ok_or_else(|| error_with_location(input, span, "comptime is missing its expression"))?;
+
+    let lambda_scope = scope.create_child();
+    let body_idx = parse_expr(expr, pool, &lambda_scope)?;
+    let start = body_idx.0 + 1 - pool.len(body_idx);
+    for i in start..=body_idx.0 {
+        if let Ast::ParamRef { name, level, .. } = pool[AstIdx(i)]
+            && level <= scope.level
+        {
             return Err(error_with_location(
                 input,
-                op_span,
-                &format!("Expected operator but found {:?}", op.as_rule()),
+                span,
+                &format!(
+                    "comptime block uses `{}`, which is only known at runtime",
+                    pool.get_string(name)
+                ),
             ));
         }
+    }
+    let lambda_idx = pool.add_lambda(0, body_idx);
 
-        let op_str = op.as_str();
+    let total_len = pool.len(lambda_idx) + 1;
+    let func_idx = pool.add_primitive_func(PrimitiveFunc::Comptime);
+    let call_idx = pool.add_call(func_idx, lambda_idx, 1, total_len);
 
-        // Get the right operand
-        let right_operand = pairs.next().ok_or_else(|| {
-            error_with_location(
+    let (line, column) = span.start_pos().line_col();
+    pool.comptime_blocks
+        .insert(call_idx, ComptimeBlock { line, column });
+    Ok(call_idx)
+}
+
+/// `yield e; rest` is a call to the `yield` primitive with `e` and a lambda
+/// without parameters computing `rest`, the continuation after the yield.
+/// Without a rest the sequence ends, the rest is `#[]`. Only `rest` is
+/// suspended: a yield nested in a larger expression is a sequence value
+/// there and does not pause the enclosing function.
+fn parse_yield(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut exprs = pair.into_inner().filter(|p| p.as_rule() == Rule::expr);
+
+    let value = exprs
+        .next()
+        .ok_or_else(|| error_with_location(input, span, "yield is missing its value"))?;
+    let value_idx = parse_expr(value, pool, scope)?;
+
+    let rest_scope = scope.create_child();
+    let rest_idx = match exprs.next() {
+        Some(rest) => parse_expr(rest, pool, &rest_scope)?,
+        None => {
+            let func_idx = pool.add_primitive_func(PrimitiveFunc::ArrayNew);
+            pool.add_call(func_idx, func_idx, 0, 1)
+        }
+    };
+    let lambda_idx = pool.add_lambda(0, rest_idx);
+
+    let total_len = pool.len(value_idx) + pool.len(lambda_idx) + 1;
+    let func_idx = pool.add_primitive_func(PrimitiveFunc::Yield);
+    Ok(pool.add_call(func_idx, lambda_idx, 2, total_len))
+}
+
+/// `perform Ask(x)` is a call to the variadic `perform` primitive with the
+/// effect and its arguments
+fn parse_perform(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut pairs = pair
+        .into_inner()
+        .filter(|p| p.as_rule() != Rule::perform_keyword);
+
+    let effect = pairs
+        .next()
+        .ok_or_else(|| error_with_location(input, span, "perform is missing its effect"))?;
+
+    let old_len = pool.nodes.len();
+    pool.add_user_func(effect.as_str());
+    let mut arg_count = 1;
+    for arg in pairs {
+        parse_expr(arg, pool, scope)?;
+        arg_count += 1;
+    }
+
+    let len = pool.nodes.len() - old_len;
+    let child_start = AstIdx(pool.nodes.len() - 1);
+    let func_idx = pool.add_primitive_func(PrimitiveFunc::Perform);
+    Ok(pool.add_call(func_idx, child_start, arg_count, len + 1))
+}
+
+/// `handle e with { Ask(x) k => h }` is a call to the variadic `handle`
+/// primitive with a lambda computing `e`, then each effect followed by a
+/// lambda taking its arguments and the continuation
+fn parse_handle(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut pairs = pair
+        .into_inner()
+        .filter(|p| p.as_rule() != Rule::handle_keyword);
+
+    let body = pairs
+        .next()
+        .ok_or_else(|| error_with_location(input, span, "handle is missing its expression"))?;
+
+    let old_len = pool.nodes.len();
+    let body_idx = parse_expr(body, pool, &scope.create_child())?;
+    pool.add_lambda(0, body_idx);
+    let mut arg_count = 1;
+
+    for clause in pairs {
+        let clause_span = clause.as_span();
+        let mut names: Vec<Pair<Rule>> = clause.into_inner().collect();
+        let (Some(clause_body), true) = (names.pop(), names.len() >= 2) else {
+            return Err(error_with_location(
                 input,
-                op_span,
-                &format!("Operator '{}' is missing its right operand", op_str),
-            )
-        })?;
+                clause_span,
+                "Handler clause needs an effect, a continuation and a body",
+            ));
+        };
 
-        let right = parse_expr(right_operand, pool, scope)?;
+        pool.add_user_func(names.remove(0).as_str());
 
-        // Fix: Calculate the length properly - it should be the length of this expression
-        let left_len = pool.len(left);
-        let right_len = pool.len(right);
-        let total_len = left_len + right_len + 1; // +1 for the operation itself
+        // The arguments of the effect followed by the continuation
+        let mut clause_scope = scope.create_child();
+        for (offset, name) in names.iter().enumerate() {
+            let name_idx = pool.intern_string(name.as_str());
+            clause_scope.add_param(name.as_str(), offset, name_idx);
+        }
+        let clause_body_idx = parse_expr(clause_body, pool, &clause_scope)?;
+        pool.add_lambda(names.len(), clause_body_idx);
+        arg_count += 2;
+    }
 
-        // Create the appropriate operation based on the operator
-        match op_str {
-            "+" => {
-                left = pool.add_add(right, total_len);
-            }
-            "*" => {
-                left = pool.add_multiply(right, total_len);
-            }
-            _ => {
-                return Err(error_with_location(
-                    input,
-                    op_span,
-                    &format!(
-                        "Unsupported operator: '{}' - only +, -, *, / are supported",
-                        op_str
-                    ),
-                ))
-            }
+    let len = pool.nodes.len() - old_len;
+    let child_start = AstIdx(pool.nodes.len() - 1);
+    let func_idx = pool.add_primitive_func(PrimitiveFunc::Handle);
+    Ok(pool.add_call(func_idx, child_start, arg_count, len + 1))
+}
+
+/// `#[a, b]` is a call to the variadic `array` primitive and `#{k: v}` one
+/// to `map_of` with keys and values alternating
+fn parse_collection_literal(
+    pair: Pair<Rule>,
+    func: PrimitiveFunc,
+    pool: &mut AstPool,
+    scope: &Scope,
+) -> Result<AstIdx> {
+    let old_len = pool.nodes.len();
+    let mut item_count = 0;
+    for item in pair.into_inner() {
+        parse_expr(item, pool, scope)?;
+        item_count += 1;
+    }
+
+    let len = pool.nodes.len() - old_len;
+    let child_start = AstIdx(pool.nodes.len().saturating_sub(1));
+    let func_idx = pool.add_primitive_func(func);
+    Ok(pool.add_call(func_idx, child_start, item_count, len + 1))
+}
+
+/// `try { body } catch err { handler }`, the handler is a lambda taking the
+/// error
+fn parse_try_catch(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut pairs = pair.into_inner();
+
+    let (Some(body_pair), Some(handler_pair)) = (pairs.next(), pairs.next()) else {
+        return Err(error_with_location(
+            input,
+            span,
+            "try expression needs a body and a catch handler",
+        ));
+    };
+
+    let body_idx = parse_expr(body_pair, pool, scope)?;
+    let handler_idx = parse_lambda(handler_pair, pool, scope)?;
+    Ok(pool.add_try_catch(body_idx, handler_idx))
+}
+
+fn parse_operator(pair: &Pair<Rule>, input: &str, pool: &AstPool) -> Result<OperatorDef> {
+    pool.get_operator(pair.as_str()).ok_or_else(|| {
+        error_with_location(
+            input,
+            pair.as_span(),
+            &format!(
+                "Unknown operator '{}' - declare it with e.g. `infixl 6 {} = function`",
+                pair.as_str(),
+                pair.as_str()
+            ),
+        )
+    })
+}
+
+fn add_operator_func(pool: &mut AstPool, def: OperatorDef) -> AstIdx {
+    let func_name = pool.get_string(def.func_name).to_string();
+    pool.add_func_ref(&func_name)
+}
+
+/// `(+)` is the operator itself and `(* e)` is `lambda x { x * e }`, the
+/// operand being evaluated in the enclosing scope and captured.
+fn parse_operator_section(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut pairs = pair.into_inner();
+
+    let op = pairs.next().ok_or_else(|| {
+        error_with_location(input, span, "Operator section is missing its operator")
+    })?;
+    let def = parse_operator(&op, input, pool)?;
+
+    let Some(operand) = pairs.next() else {
+        return Ok(add_operator_func(pool, def));
+    };
+
+    let section_level = scope.level + 1;
+    let param_name_idx = pool.intern_string("_");
+    let param = pool.add_param_ref(param_name_idx, section_level, 0);
+    let right = parse_expr(operand, pool, scope)?;
+
+    let total_len = pool.len(param) + pool.len(right) + 1;
+    let func_idx = add_operator_func(pool, def);
+    let body_idx = pool.add_call(func_idx, right, 2, total_len);
+
+    Ok(pool.add_lambda(1, body_idx))
+}
+
+/// A parenthesized expression, or a left section `(e *)` when followed by an
+/// operator, which is the operator partially applied to `e`.
+fn parse_parenthesized(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let span = pair.as_span();
+    let mut pairs = pair.into_inner();
+
+    let operand = pairs.next().ok_or_else(|| {
+        error_with_location(input, span, "Empty expression where a value was expected")
+    })?;
+    let left = parse_expr(operand, pool, scope)?;
+
+    let Some(op) = pairs.next() else {
+        return Ok(left);
+    };
+    let def = parse_operator(&op, input, pool)?;
+
+    let total_len = pool.len(left) + 1;
+    let func_idx = add_operator_func(pool, def);
+    Ok(pool.add_call(func_idx, left, 1, total_len))
+}
+
+fn parse_binary_expr(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> Result<AstIdx> {
+    let input = pair.as_str();
+    let mut pairs = pair.into_inner().peekable();
+
+    parse_infix(input, &mut pairs, 0, None, pool, scope)
+}
+
+/// Precedence climbing over `operand (op operand)*`. Consumes operators
+/// binding at least as tightly as `min_precedence`, `previous` is the
+/// operator whose right operand is being parsed.
+fn parse_infix(
+    input: &str,
+    pairs: &mut Peekable<Pairs<Rule>>,
+    min_precedence: u8,
+    mut previous: Option<OperatorDef>,
+    pool: &mut AstPool,
+    scope: &Scope,
+) -> Result<AstIdx> {
+    let first = pairs.next().ok_or(ParserError::InvalidBinaryExpr)?;
+    let mut left = parse_expr(first, pool, scope)?;
+
+    while let Some(op) = pairs.peek() {
+        let def = parse_operator(op, input, pool)?;
+        if def.precedence < min_precedence {
+            break;
         }
+
+        // Non-associative operators and operators of equal precedence but
+        // different associativity don't group without parentheses
+        if let Some(prev) = previous
+            && prev.precedence == def.precedence
+            && (prev.fixity != def.fixity || def.fixity == Fixity::None)
+        {
+            return Err(error_with_location(
+                input,
+                op.as_span(),
+                &format!(
+                    "Operator '{}' cannot be chained with the preceding operator of precedence {} - add parentheses",
+                    op.as_str(),
+                    def.precedence
+                ),
+            ));
+        }
+        pairs.next();
+
+        let next_min = match def.fixity {
+            Fixity::Right => def.precedence,
+            Fixity::Left | Fixity::None => def.precedence.saturating_add(1),
+        };
+        let right = parse_infix(input, pairs, next_min, Some(def), pool, scope)?;
+
+        let total_len = pool.len(left) + pool.len(right) + 1;
+        let func_idx = add_operator_func(pool, def);
+        left = pool.add_call(func_idx, right, 2, total_len);
+        previous = Some(def);
     }
 
     Ok(left)
@@ -390,12 +1238,12 @@ fn parse_function_call(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> R
 
     let func_name = identifier.as_str();
 
-    let mut func_idx = 0.into();
-    let mut args_arglen_argstart = Vec::new();
-
+    // Each argument list is followed by its call node so that every call
+    // covers a contiguous range of the pool: callee, arguments, call.
     let old_len = pool.nodes.len();
+    let mut func_idx = None;
 
-    for (i, args_pair) in pairs.enumerate() {
+    for args_pair in pairs {
         if args_pair.as_rule() != Rule::argument_list {
             return Err(error_with_location(
                 input,
@@ -403,25 +1251,153 @@ fn parse_function_call(pair: Pair<Rule>, pool: &mut AstPool, scope: &Scope) -> R
                 &format!("Expected argument list but found {:?}", args_pair.as_rule()),
             ));
         }
-        let mut arg = 0;
 
-        for arg_pair in args_pair.into_inner() {
-            let arg_idx = parse_expr(arg_pair, pool, scope)?;
-            println!("Argument: {:?} - {:?}", arg_idx, &pool[arg_idx]);
+        // A macro call is replaced by its expansion, which the following
+        // argument lists are applied to
+        let macro_def = match (func_idx, scope.lookup(func_name)) {
+            (None, None) => {
+                let name_idx = pool.intern_string(func_name);
+                pool.macros.get(&name_idx).cloned()
+            }
+            _ => None,
+        };
+        if let Some(macro_def) = macro_def {
+            func_idx = Some(parse_macro_call(
+                args_pair, func_name, &macro_def, pool, scope,
+            )?);
+            continue;
+        }
+
+        // Only the first argument list of a call to a top-level function
+        // knows its parameters
+        let callee = match (func_idx, scope.lookup(func_name)) {
+            (None, None) => Some(pool.intern_string(func_name)),
+            _ => None,
+        };
+        let (arg_count, named_args) = parse_arguments(args_pair, callee, pool, scope)?;
 
-            arg += 1;
+        let len = pool.nodes.len() - old_len;
+        let child_start = AstIdx(pool.nodes.len().saturating_sub(1));
+
+        let call_idx = match func_idx {
+            None => match scope.lookup(func_name) {
+                Some((offset, (level, name_idx))) => {
+                    let callee = pool.add_param_ref(name_idx, level, offset);
+                    pool.add_lambda_call(callee, child_start, arg_count, len + 1)
+                }
+                None => pool.add_function_call(func_name, child_start, arg_count, len + 1),
+            },
+            Some(callee) => pool.add_lambda_call(callee, child_start, arg_count, len),
+        };
+
+        if let Some(named_args) = named_args {
+            pool.named_args.insert(call_idx, named_args);
         }
-        let curr_len = pool.nodes.len();
+        func_idx = Some(call_idx);
+    }
 
-        args_arglen_argstart.push((arg, curr_len - old_len, AstIdx(curr_len - 1)));
+    func_idx
+        .ok_or_else(|| error_with_location(input, span, "Function call is missing its arguments"))
+}
+
+/// Parse a macro call as written, followed by its expansion
+fn parse_macro_call(
+    args_pair: Pair<Rule>,
+    name: &str,
+    macro_def: &MacroDef,
+    pool: &mut AstPool,
+    scope: &Scope,
+) -> Result<AstIdx> {
+    let input = args_pair.as_str();
+    let span = args_pair.as_span();
+
+    let old_len = pool.nodes.len();
+    let mut arg_count = 0;
+    for arg_pair in args_pair.into_inner() {
+        if arg_pair.as_rule() == Rule::named_argument {
+            return Err(error_with_location(
+                input,
+                arg_pair.as_span(),
+                &format!("Macro '{}' cannot be given named arguments", name),
+            ));
+        }
+        parse_expr(arg_pair, pool, scope)?;
+        arg_count += 1;
     }
 
-    for (i, (a, alen, astart)) in args_arglen_argstart.iter().enumerate() {
-        if i == 0 {
-            func_idx = pool.add_function_call(func_name, *astart, *a, *alen + 1);
+    if arg_count != macro_def.params.len() {
+        return Err(error_with_location(
+            input,
+            span,
+            &format!(
+                "Macro '{}' expects {} arguments but got {}",
+                name,
+                macro_def.params.len(),
+                arg_count
+            ),
+        ));
+    }
+
+    let len = pool.nodes.len() - old_len;
+    let child_start = AstIdx(pool.nodes.len().saturating_sub(1));
+    let call_idx = pool.add_function_call(name, child_start, arg_count, len + 1);
+    Ok(pool.add_macro_call(macro_def, call_idx, scope.level))
+}
+
+/// Parse the arguments of a call in the order they were written, positional
+/// ones first. Named arguments are recorded in the returned value, they are
+/// placed at their parameter and the defaults of the parameters left out are
+/// filled in when the call is compiled.
+fn parse_arguments(
+    args_pair: Pair<Rule>,
+    callee: Option<NameIdx>,
+    pool: &mut AstPool,
+    scope: &Scope,
+) -> Result<(usize, Option<NamedArgs>)> {
+    let input = args_pair.as_str();
+
+    let mut positional = Vec::new();
+    let mut named = Vec::new();
+    for arg_pair in args_pair.into_inner() {
+        if arg_pair.as_rule() == Rule::named_argument {
+            let mut parts = arg_pair.into_inner();
+            if let (Some(name), Some(expr)) = (parts.next(), parts.next()) {
+                named.push((name, expr));
+            }
+        } else if named.is_empty() {
+            positional.push(arg_pair);
         } else {
-            func_idx = pool.add_lambda_call(func_idx, *astart, *a, *alen + 1);
+            return Err(error_with_location(
+                input,
+                arg_pair.as_span(),
+                "Positional arguments must come before named arguments",
+            ));
+        }
+    }
+
+    let named_args = match (callee, named.first()) {
+        (_, None) => None,
+        (None, Some((name, _))) => {
+            return Err(error_with_location(
+                input,
+                name.as_span(),
+                "Named arguments can only be passed to top-level functions",
+            ));
         }
+        (Some(callee), Some(_)) => Some(NamedArgs {
+            callee,
+            positional: positional.len(),
+            names: named
+                .iter()
+                .map(|(name, _)| pool.intern_string(name.as_str()))
+                .collect(),
+        }),
+    };
+
+    let written = positional.iter().chain(named.iter().map(|(_, expr)| expr));
+    for arg_pair in written {
+        parse_expr(arg_pair.clone(), pool, scope)?;
     }
-    Ok(func_idx)
+
+    Ok((positional.len() + named.len(), named_args))
 }
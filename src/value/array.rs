@@ -0,0 +1,71 @@
+use std::rc::Rc;
+
+use crate::value::Value;
+
+/// An array made by `#[a, b, c]`. Slices share the buffer of the array they
+/// were taken from, `push` copies it unless the array is its only user.
+#[derive(Clone)]
+pub struct Array {
+    items: Rc<Vec<Value>>,
+    start: usize,
+    end: usize,
+}
+
+impl Array {
+    pub fn new(items: Vec<Value>) -> Self {
+        let end = items.len();
+        Self {
+            items: Rc::new(items),
+            start: 0,
+            end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn as_slice(&self) -> &[Value] {
+        &self.items[self.start..self.end]
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.as_slice().get(index)
+    }
+
+    /// The items from `from` up to but not including `to`
+    pub fn slice(&self, from: usize, to: usize) -> Option<Self> {
+        if from > to || to > self.len() {
+            return None;
+        }
+
+        Some(Self {
+            items: self.items.clone(),
+            start: self.start + from,
+            end: self.start + to,
+        })
+    }
+
+    pub fn push(mut self, value: Value) -> Self {
+        // A slice gets its own buffer rather than writing past its end
+        if self.start != 0 || self.end != self.items.len() {
+            self.items = Rc::new(self.as_slice().to_vec());
+            self.start = 0;
+        }
+
+        let items = Rc::make_mut(&mut self.items);
+        items.push(value);
+        self.end = items.len();
+        self
+    }
+}
+
+impl core::fmt::Debug for Array {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "#{:?}", self.as_slice())
+    }
+}
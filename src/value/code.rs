@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::CompiledFunction;
+use crate::ast::Ast;
+use crate::ast::indices::{AstIdx, NameIdx, ParamIdx};
+use crate::ast::pool::AstPool;
+use crate::ast::pretty_printer::PrettyPrinter;
+use crate::value::Value;
+
+/// Functions and constants of the program a code value was quoted in, by
+/// name, for `eval` to call
+#[derive(Default)]
+pub struct Globals {
+    pub functions: HashMap<String, CompiledFunction>,
+    pub constants: HashMap<String, Rc<RefCell<Option<Value>>>>,
+}
+
+/// An expression made by `quote { e }`, in a pool of its own. The
+/// parameters of the enclosing functions it uses are parameters of level 1
+/// in the fragment, which is compiled like the body of a function taking
+/// their values.
+#[derive(Clone)]
+pub struct Code {
+    pool: Rc<AstPool>,
+    root: AstIdx,
+    /// Names of the captured parameters, in order
+    params: Rc<[NameIdx]>,
+    /// Values of the captured parameters, in order
+    env: Rc<[Value]>,
+    globals: Rc<Globals>,
+}
+
+impl Code {
+    pub fn new(
+        pool: AstPool,
+        root: AstIdx,
+        params: Vec<NameIdx>,
+        env: Vec<Value>,
+        globals: Rc<Globals>,
+    ) -> Self {
+        Self {
+            pool: Rc::new(pool),
+            root,
+            params: Rc::from(params),
+            env: Rc::from(env),
+            globals,
+        }
+    }
+
+    /// A pool for a fragment, with the name its printer works under
+    pub fn fragment_pool() -> AstPool {
+        let mut pool = AstPool::new();
+        pool.intern_string("quote");
+        pool
+    }
+
+    /// The code of an integer literal
+    pub fn int(value: i64) -> Self {
+        let mut pool = Self::fragment_pool();
+        let root = pool.add_integer(value);
+        Self::new(pool, root, Vec::new(), Vec::new(), Rc::default())
+    }
+
+    /// The same code with other values for its captured parameters
+    pub fn with_env(&self, env: Vec<Value>) -> Self {
+        Self {
+            env: Rc::from(env),
+            ..self.clone()
+        }
+    }
+
+    pub fn pool(&self) -> &AstPool {
+        &self.pool
+    }
+
+    pub fn root(&self) -> AstIdx {
+        self.root
+    }
+
+    pub fn env(&self) -> &[Value] {
+        &self.env
+    }
+
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+
+    /// The node that runs, seeing through macro calls
+    fn node(&self) -> AstIdx {
+        let mut node = self.root;
+        while let Ast::MacroCall { expansion_idx, .. } = self.pool[node] {
+            node = expansion_idx;
+        }
+        node
+    }
+
+    /// What kind of expression the code is
+    pub fn kind(&self) -> &'static str {
+        match self.pool[self.node()] {
+            Ast::Integer(_) => "int",
+            Ast::ParamRef { .. } => "param",
+            Ast::UserFunc(_) => "function",
+            Ast::PrimitiveFunc(_) => "primitive",
+            Ast::Lambda { .. } => "lambda",
+            Ast::Call { .. } => "call",
+            _ => "other",
+        }
+    }
+
+    /// The callee and the arguments of a call, nothing for other codes
+    pub fn parts(&self) -> Vec<Code> {
+        let node = self.node();
+        let Ast::Call { func_idx, .. } = self.pool[node] else {
+            return Vec::new();
+        };
+
+        let mut parts = vec![func_idx];
+        parts.extend(self.pool.children(node).unwrap_or_default());
+        parts
+            .into_iter()
+            .map(|part| {
+                let mut pool = Self::fragment_pool();
+                let root = self
+                    .pool
+                    .copy_into(part, &mut pool, |level, offset| (level, offset));
+                Self {
+                    pool: Rc::new(pool),
+                    root,
+                    ..self.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// The code of a call of `callee` with `args`, which keeps the
+    /// parameters captured by all of them
+    pub fn call(callee: &Code, args: &[Code]) -> Self {
+        let mut pool = Self::fragment_pool();
+        let mut params = Vec::new();
+        let mut env = Vec::new();
+        let mut globals = Globals::default();
+
+        let mut copy = |code: &Code, pool: &mut AstPool| {
+            let env_start = env.len();
+            let root = code
+                .pool
+                .copy_into(code.root, pool, |level, offset| match level {
+                    1 => (1, ParamIdx(offset.0 + env_start)),
+                    _ => (level, offset),
+                });
+            params.extend(
+                code.params
+                    .iter()
+                    .map(|&name| pool.intern_string(code.pool.get_string(name))),
+            );
+            env.extend(code.env.iter().cloned());
+            for (name, function) in &code.globals.functions {
+                globals.functions.insert(name.clone(), function.clone());
+            }
+            for (name, slot) in &code.globals.constants {
+                globals.constants.insert(name.clone(), slot.clone());
+            }
+            root
+        };
+
+        // Arguments come before the callee, like in parsed calls
+        let start = pool.nodes.len();
+        let mut last_arg = None;
+        for arg in args {
+            last_arg = Some(copy(arg, &mut pool));
+        }
+        let func_idx = copy(callee, &mut pool);
+        let len = pool.nodes.len() - start;
+        let root = pool.add_call(func_idx, last_arg.unwrap_or(func_idx), args.len(), len);
+
+        Self::new(pool, root, params, env, Rc::new(globals))
+    }
+}
+
+impl core::fmt::Debug for Code {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let printer = PrettyPrinter::new(&self.pool);
+        let code = match self.pool.get_name_idx_from_func("quote") {
+            Some(owner) => printer.print_template(owner, &self.params, self.root),
+            None => printer.print_node(self.root),
+        };
+        write!(f, "quote {{ {code} }}")
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::compiler::function::ErrTrace;
+use crate::value::Value;
+
+/// The hashable form of a value used as a map key or as the arguments of a
+/// memoised call. Lists and arrays of hashable values are keys too,
+/// standing in for tuples.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum MapKey {
+    Unit,
+    Bool(bool),
+    Char(char),
+    Int(i64),
+    Str(Rc<str>),
+    /// Lists and arrays with the same items are different keys, as they are
+    /// different values
+    List(Rc<[MapKey]>),
+    Array(Rc<[MapKey]>),
+}
+
+impl MapKey {
+    pub(crate) fn new(value: &Value) -> Result<Self, ErrTrace> {
+        let seq = |items: &[Value]| -> Result<Rc<[MapKey]>, ErrTrace> {
+            items.iter().map(Self::new).collect()
+        };
+
+        match value {
+            Value::Unit => Ok(Self::Unit),
+            Value::Bool(b) => Ok(Self::Bool(*b)),
+            Value::Char(c) => Ok(Self::Char(*c)),
+            Value::Int(i) => Ok(Self::Int(*i)),
+            Value::Str(s) => Ok(Self::Str(s.clone())),
+            Value::List(items) => Ok(Self::List(seq(items)?)),
+            Value::Array(array) => Ok(Self::Array(seq(array.as_slice())?)),
+            Value::Fun(_) => Err(ErrTrace::new("cannot hash a function")),
+            other => Err(ErrTrace::new(format!("cannot hash {:?}", other))),
+        }
+    }
+}
+
+/// A map made by `#{k: v}`. Updates return a new map and leave the old one
+/// as it was, the entries are only copied when both are still in use.
+#[derive(Clone, Default)]
+pub struct Map {
+    /// Entries keep the key as it was given to return it from `keys`
+    entries: Rc<HashMap<MapKey, (Value, Value)>>,
+}
+
+impl Map {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, ErrTrace> {
+        let key = MapKey::new(key)?;
+        Ok(self.entries.get(&key).map(|(_, value)| value))
+    }
+
+    pub fn contains(&self, key: &Value) -> Result<bool, ErrTrace> {
+        Ok(self.entries.contains_key(&MapKey::new(key)?))
+    }
+
+    pub fn insert(mut self, key: Value, value: Value) -> Result<Self, ErrTrace> {
+        let hashed = MapKey::new(&key)?;
+        Rc::make_mut(&mut self.entries).insert(hashed, (key, value));
+        Ok(self)
+    }
+
+    pub fn remove(mut self, key: &Value) -> Result<Self, ErrTrace> {
+        let hashed = MapKey::new(key)?;
+        if self.entries.contains_key(&hashed) {
+            Rc::make_mut(&mut self.entries).remove(&hashed);
+        }
+        Ok(self)
+    }
+
+    /// The entries ordered by key, so that output does not depend on hashing
+    pub fn entries(&self) -> Vec<(&Value, &Value)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|&(key, _)| key);
+        entries
+            .into_iter()
+            .map(|(_, (key, value))| (key, value))
+            .collect()
+    }
+}
+
+impl core::fmt::Debug for Map {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "#{{")?;
+        for (i, (key, value)) in self.entries().into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{key:?}: {value:?}")?;
+        }
+        write!(f, "}}")
+    }
+}
@@ -1,3 +1,19 @@
+pub mod array;
+pub mod code;
+pub mod map;
+pub mod seq;
+pub mod thunk;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub use self::array::Array;
+pub use self::code::Code;
+pub use self::map::Map;
+pub use self::seq::Seq;
+pub use self::thunk::Thunk;
+
+use crate::compiler::function::ErrTrace;
 use crate::CompiledFunction;
 
 #[derive(Clone)]
@@ -7,6 +23,82 @@ pub enum Value {
     Bool(bool),
     Char(char),
     Fun(CompiledFunction),
+    List(Rc<[Value]>),
+    /// `some(x)` or `none`
+    Option(Option<Rc<Value>>),
+    /// `ok(x)` or `err(e)`
+    Result(Result<Rc<Value>, Rc<Value>>),
+    Str(Rc<str>),
+    /// A runtime error caught by `try`/`catch`
+    Error(Rc<ErrTrace>),
+    /// A mutable cell made by `ref(v)`, shared by all its copies
+    Ref(Rc<RefCell<Value>>),
+    Array(Array),
+    Map(Map),
+    Seq(Seq),
+    Thunk(Thunk),
+    /// An effect declared with `effect`, given to `perform` and `handle`
+    Effect(Rc<str>),
+    /// An expression made by `quote { e }`
+    Code(Code),
+}
+
+impl Value {
+    /// Compares values by their contents, except references which are only
+    /// equal to themselves. Functions and values of different kinds cannot
+    /// be compared.
+    pub fn equals(&self, other: &Value) -> Result<bool, ErrTrace> {
+        let all_equal = |a: &[Value], b: &[Value]| -> Result<bool, ErrTrace> {
+            if a.len() != b.len() {
+                return Ok(false);
+            }
+            for (a, b) in a.iter().zip(b) {
+                if !a.equals(b)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        };
+
+        match (self, other) {
+            (Value::Unit, Value::Unit) => Ok(true),
+            (Value::Int(a), Value::Int(b)) => Ok(a == b),
+            (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+            (Value::Char(a), Value::Char(b)) => Ok(a == b),
+            (Value::Str(a), Value::Str(b)) => Ok(a == b),
+            // References are the same only if they are the same cell
+            (Value::Ref(a), Value::Ref(b)) => Ok(Rc::ptr_eq(a, b)),
+            (Value::List(a), Value::List(b)) => all_equal(a, b),
+            (Value::Array(a), Value::Array(b)) => all_equal(a.as_slice(), b.as_slice()),
+            (Value::Option(None), Value::Option(None)) => Ok(true),
+            (Value::Option(Some(a)), Value::Option(Some(b))) => a.equals(b),
+            (Value::Option(_), Value::Option(_)) => Ok(false),
+            (Value::Result(Ok(a)), Value::Result(Ok(b)))
+            | (Value::Result(Err(a)), Value::Result(Err(b))) => a.equals(b),
+            (Value::Result(_), Value::Result(_)) => Ok(false),
+            (Value::Map(a), Value::Map(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (key, value) in a.entries() {
+                    match b.get(key)? {
+                        Some(other) if value.equals(other)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+            (a, b) => Err(ErrTrace::new(format!(
+                "Cannot compare {:?} and {:?} for equality",
+                a, b
+            ))),
+        }
+    }
+}
+
+thread_local! {
+    /// The references whose contents are being printed
+    static PRINTED_REFS: RefCell<Vec<*const RefCell<Value>>> = const { RefCell::new(Vec::new()) };
 }
 
 impl core::fmt::Debug for Value {
@@ -17,6 +109,37 @@ impl core::fmt::Debug for Value {
             Value::Bool(i) => write!(f, "{i} : Bool"),
             Value::Char(i) => write!(f, "{i} : Char"),
             Value::Fun(_compiled_function) => write!(f, "Function"),
+            Value::List(items) => write!(f, "{items:?} : List"),
+            Value::Option(Some(value)) => write!(f, "some({value:?}) : Option"),
+            Value::Option(None) => write!(f, "none : Option"),
+            Value::Result(Ok(value)) => write!(f, "ok({value:?}) : Result"),
+            Value::Result(Err(error)) => write!(f, "err({error:?}) : Result"),
+            Value::Str(s) => write!(f, "{s:?} : Str"),
+            Value::Error(error) => write!(f, "error({:?}) : Error", error.describe()),
+            Value::Ref(cell) => {
+                // A reference may hold itself, which is printed as `ref(..)`
+                let cell_ptr = Rc::as_ptr(cell);
+                if PRINTED_REFS.with(|refs| refs.borrow().contains(&cell_ptr)) {
+                    return write!(f, "ref(..) : Ref");
+                }
+                let Ok(value) = cell.try_borrow() else {
+                    return write!(f, "ref(..) : Ref");
+                };
+                PRINTED_REFS.with(|refs| refs.borrow_mut().push(cell_ptr));
+                let result = write!(f, "ref({value:?}) : Ref");
+                PRINTED_REFS.with(|refs| refs.borrow_mut().pop());
+                result
+            }
+            Value::Array(array) => write!(f, "{array:?} : Array"),
+            Value::Map(map) => write!(f, "{map:?} : Map"),
+            // Printing the elements would run the sequence
+            Value::Seq(_) => write!(f, "Sequence"),
+            Value::Effect(name) => write!(f, "{name} : Effect"),
+            Value::Code(code) => write!(f, "{code:?} : Code"),
+            Value::Thunk(thunk) => match thunk.value() {
+                Some(value) => write!(f, "lazy({value:?}) : Lazy"),
+                None => write!(f, "lazy(?) : Lazy"),
+            },
         }
     }
 }
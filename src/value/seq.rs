@@ -0,0 +1,161 @@
+use std::rc::Rc;
+
+use crate::CompiledFunction;
+use crate::compiler::function::{self, ErrTrace};
+use crate::value::{Array, Thunk, Value};
+
+/// The first element of a sequence and the sequence of the rest, or `None`
+/// at its end
+pub type Step = Result<Option<(Value, Seq)>, ErrTrace>;
+
+/// A lazy sequence, made by a range like `1..10` or from a list or an array.
+/// Elements are produced when they are asked for and a sequence can be
+/// walked any number of times.
+#[derive(Clone)]
+pub struct Seq {
+    next: Rc<dyn Fn() -> Step>,
+}
+
+impl Seq {
+    pub fn new(next: impl Fn() -> Step + 'static) -> Self {
+        Self {
+            next: Rc::new(next),
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self::new(|| Ok(None))
+    }
+
+    /// The integers from `from` up to `to`, including `to` when `inclusive`
+    pub fn range(from: i64, to: i64, inclusive: bool) -> Self {
+        Self::new(move || {
+            if from < to || (inclusive && from == to) {
+                let rest = match from.checked_add(1) {
+                    Some(next) => Seq::range(next, to, inclusive),
+                    None => Seq::empty(),
+                };
+                Ok(Some((Value::Int(from), rest)))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    /// The elements of a sequence, a list, an array or an Option
+    pub fn from_value(value: Value) -> Result<Self, ErrTrace> {
+        match value {
+            Value::Seq(seq) => Ok(seq),
+            Value::List(items) => Ok(Seq::from_items(items, 0)),
+            Value::Array(array) => Ok(Seq::from_items(Rc::from(array.as_slice()), 0)),
+            Value::Option(None) => Ok(Seq::empty()),
+            Value::Option(Some(value)) => Ok(Seq::from_items(Rc::from([(*value).clone()]), 0)),
+            other => Err(ErrTrace::new(format!(
+                "expected a sequence, a list or an array but got {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// `head` followed by the elements of what `rest` computes, which is
+    /// only computed once and when they are asked for
+    pub fn cons(head: Value, rest: Thunk) -> Self {
+        let rest = Seq::new(move || Seq::from_value(rest.force()?)?.next());
+        Self::new(move || Ok(Some((head.clone(), rest.clone()))))
+    }
+
+    pub fn from_items(items: Rc<[Value]>, start: usize) -> Self {
+        Self::new(move || match items.get(start) {
+            Some(item) => Ok(Some((
+                item.clone(),
+                Seq::from_items(items.clone(), start + 1),
+            ))),
+            None => Ok(None),
+        })
+    }
+
+    /// Asking for an element costs a step of a bounded evaluation
+    pub fn next(&self) -> Step {
+        function::charge_steps(1)?;
+        (self.next)()
+    }
+
+    pub fn take(self, n: usize) -> Self {
+        Self::new(move || {
+            if n == 0 {
+                return Ok(None);
+            }
+            Ok(self.next()?.map(|(item, rest)| (item, rest.take(n - 1))))
+        })
+    }
+
+    pub fn drop(self, n: usize) -> Self {
+        Self::new(move || {
+            let mut seq = self.clone();
+            for _ in 0..n {
+                match seq.next()? {
+                    Some((_, rest)) => seq = rest,
+                    None => return Ok(None),
+                }
+            }
+            seq.next()
+        })
+    }
+
+    pub fn map(self, f: CompiledFunction) -> Self {
+        Self::new(move || match self.next()? {
+            Some((item, rest)) => Ok(Some((call(&f, vec![item])?, rest.map(f.clone())))),
+            None => Ok(None),
+        })
+    }
+
+    pub fn filter(self, f: CompiledFunction) -> Self {
+        Self::new(move || {
+            let mut seq = self.clone();
+            while let Some((item, rest)) = seq.next()? {
+                match call(&f, vec![item.clone()])? {
+                    Value::Bool(true) => return Ok(Some((item, rest.filter(f.clone())))),
+                    Value::Bool(false) => seq = rest,
+                    other => {
+                        return Err(ErrTrace::new(format!(
+                            "`filter` expects its function to return a Bool but got {:?}",
+                            other
+                        )));
+                    }
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    /// Pairs of elements as two element arrays, as long as the shorter
+    /// sequence
+    pub fn zip(self, other: Seq) -> Self {
+        Self::new(move || {
+            let (Some((a, rest_a)), Some((b, rest_b))) = (self.next()?, other.next()?) else {
+                return Ok(None);
+            };
+            let pair = Value::Array(Array::new(vec![a, b]));
+            Ok(Some((pair, rest_a.zip(rest_b))))
+        })
+    }
+
+    pub fn collect(&self) -> Result<Vec<Value>, ErrTrace> {
+        let mut items = Vec::new();
+        let mut seq = self.clone();
+        while let Some((item, rest)) = seq.next()? {
+            items.push(item);
+            seq = rest;
+        }
+        Ok(items)
+    }
+}
+
+/// Apply `f` to `args` on a stack of its own
+fn call(f: &CompiledFunction, mut args: Vec<Value>) -> Result<Value, ErrTrace> {
+    let argc = args.len();
+    // The first argument goes on top
+    args.reverse();
+    f.apply(&mut args, argc)?;
+    args.pop().ok_or(ErrTrace::new("stack underflow"))
+}
@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::CompiledFunction;
+use crate::compiler::function::ErrTrace;
+use crate::value::Value;
+
+enum ThunkState {
+    /// The function without parameters computing the value
+    Pending(CompiledFunction),
+    Forcing,
+    Done(Value),
+}
+
+/// A value made by `lazy e`, computed by the first `force` and remembered
+/// for the later ones. Copies of a thunk share the result.
+#[derive(Clone)]
+pub struct Thunk {
+    state: Rc<RefCell<ThunkState>>,
+}
+
+impl Thunk {
+    pub fn new(compute: CompiledFunction) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(ThunkState::Pending(compute))),
+        }
+    }
+
+    pub fn force(&self) -> Result<Value, ErrTrace> {
+        let state = std::mem::replace(&mut *self.state.borrow_mut(), ThunkState::Forcing);
+        let compute = match state {
+            ThunkState::Pending(compute) => compute,
+            ThunkState::Done(value) => {
+                *self.state.borrow_mut() = ThunkState::Done(value.clone());
+                return Ok(value);
+            }
+            ThunkState::Forcing => {
+                return Err(ErrTrace::new("lazy value depends on itself"));
+            }
+        };
+
+        let mut mem = Vec::new();
+        match compute.apply(&mut mem, 0).and_then(|()| {
+            mem.pop()
+                .ok_or(ErrTrace::new("lazy value produced no result"))
+        }) {
+            Ok(value) => {
+                *self.state.borrow_mut() = ThunkState::Done(value.clone());
+                Ok(value)
+            }
+            Err(error) => {
+                // A failed computation is tried again by the next `force`
+                *self.state.borrow_mut() = ThunkState::Pending(compute);
+                Err(error)
+            }
+        }
+    }
+
+    /// The value if it was already computed
+    pub fn value(&self) -> Option<Value> {
+        match &*self.state.borrow() {
+            ThunkState::Done(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}